@@ -0,0 +1,12 @@
+
+-- Arrival time at the ingestor, used by the keep-latest-by-arrival conflict policy
+ALTER TABLE telemetry ADD COLUMN IF NOT EXISTS received_at TIMESTAMPTZ;
+
+-- Sequence number for readings kept side by side by the keep-both conflict policy
+ALTER TABLE telemetry ADD COLUMN IF NOT EXISTS seq INTEGER NOT NULL DEFAULT 0;
+
+-- (device_id, ts) stays unique for the primary reading so ON CONFLICT can target it
+DROP INDEX IF EXISTS ux_device_ts;
+CREATE UNIQUE INDEX IF NOT EXISTS ux_device_ts ON telemetry(device_id, ts) WHERE seq = 0;
+
+CREATE UNIQUE INDEX IF NOT EXISTS ux_device_ts_seq ON telemetry(device_id, ts, seq);
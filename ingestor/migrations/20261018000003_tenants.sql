@@ -0,0 +1,15 @@
+-- Owning tenant of each reading; rows from before tenants existed belong to the default tenant
+ALTER TABLE telemetry ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+
+-- Device ids are only unique within a tenant
+DROP INDEX IF EXISTS ux_device_ts;
+DROP INDEX IF EXISTS ux_device_ts_seq;
+CREATE UNIQUE INDEX IF NOT EXISTS ux_tenant_device_ts ON telemetry(tenant_id, device_id, ts) WHERE seq = 0;
+CREATE UNIQUE INDEX IF NOT EXISTS ux_tenant_device_ts_seq ON telemetry(tenant_id, device_id, ts, seq);
+
+DROP INDEX IF EXISTS idx_device_ts;
+CREATE INDEX IF NOT EXISTS idx_tenant_device_ts ON telemetry (tenant_id, device_id, ts DESC);
+CREATE INDEX IF NOT EXISTS idx_tenant_ts ON telemetry (tenant_id, ts DESC);
+
+-- API keys bound to a tenant only see that tenant's data; NULL sees every tenant
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS tenant_id TEXT;
@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+/// Bounds and target for adaptive batching
+#[derive(Debug, Clone)]
+pub struct AdaptiveConfig {
+    pub min_batch: usize,
+    pub max_batch: usize,
+    pub min_wait_ms: u64,
+    pub max_wait_ms: u64,
+    pub target_latency_ms: u64,
+}
+
+/// AIMD controller for batch size and flush interval.
+///
+/// Each flush reports its insert latency and the worker's share of the ingest
+/// channel backlog. When latency exceeds the target both parameters are halved;
+/// when there is headroom and a backlog is building they grow by a fixed step.
+#[derive(Debug, Clone)]
+pub struct AdaptiveController {
+    config: AdaptiveConfig,
+    batch_size: usize,
+    wait_ms: u64,
+    batch_step: usize,
+}
+
+/// Queue fill ratio above which the worker counts as backlogged
+const BACKLOG_RATIO: f64 = 0.5;
+
+impl AdaptiveController {
+    pub fn new(mut config: AdaptiveConfig, initial_batch: usize, initial_wait_ms: u64) -> Self {
+        config.max_batch = config.max_batch.max(1);
+        config.min_batch = config.min_batch.clamp(1, config.max_batch);
+        // A zero flush interval would make the worker's ticker panic
+        config.max_wait_ms = config.max_wait_ms.max(1);
+        config.min_wait_ms = config.min_wait_ms.clamp(1, config.max_wait_ms);
+        let batch_step = ((config.max_batch - config.min_batch) / 20).max(1);
+        let batch_size = initial_batch.clamp(config.min_batch, config.max_batch);
+        let wait_ms = initial_wait_ms.clamp(config.min_wait_ms, config.max_wait_ms);
+        Self {
+            config,
+            batch_size,
+            wait_ms,
+            batch_step,
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn wait(&self) -> Duration {
+        Duration::from_millis(self.wait_ms)
+    }
+
+    /// Feeds one flush observation into the controller
+    pub fn observe(&mut self, latency: Duration, queue_depth: usize, queue_capacity: usize) {
+        let target = Duration::from_millis(self.config.target_latency_ms);
+
+        if latency > target {
+            // Multiplicative decrease
+            self.batch_size = (self.batch_size / 2).max(self.config.min_batch);
+            self.wait_ms = (self.wait_ms / 2).max(self.config.min_wait_ms);
+            return;
+        }
+
+        let fill = if queue_capacity == 0 {
+            0.0
+        } else {
+            queue_depth as f64 / queue_capacity as f64
+        };
+
+        if fill >= BACKLOG_RATIO || queue_depth >= self.batch_size {
+            // Additive increase
+            self.batch_size = (self.batch_size + self.batch_step).min(self.config.max_batch);
+            self.wait_ms = (self.wait_ms + 1).min(self.config.max_wait_ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> AdaptiveController {
+        AdaptiveController::new(
+            AdaptiveConfig {
+                min_batch: 100,
+                max_batch: 2100,
+                min_wait_ms: 5,
+                max_wait_ms: 100,
+                target_latency_ms: 50,
+            },
+            1000,
+            20,
+        )
+    }
+
+    #[test]
+    fn test_decrease_on_slow_flush() {
+        let mut c = controller();
+        c.observe(Duration::from_millis(80), 0, 1000);
+        assert_eq!(c.batch_size(), 500);
+        assert_eq!(c.wait(), Duration::from_millis(10));
+
+        for _ in 0..10 {
+            c.observe(Duration::from_millis(80), 0, 1000);
+        }
+        assert_eq!(c.batch_size(), 100);
+        assert_eq!(c.wait(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_increase_on_backlog() {
+        let mut c = controller();
+        c.observe(Duration::from_millis(10), 800, 1000);
+        assert_eq!(c.batch_size(), 1100);
+        assert_eq!(c.wait(), Duration::from_millis(21));
+
+        for _ in 0..100 {
+            c.observe(Duration::from_millis(10), 800, 1000);
+        }
+        assert_eq!(c.batch_size(), 2100);
+        assert_eq!(c.wait(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_hold_when_idle() {
+        let mut c = controller();
+        c.observe(Duration::from_millis(10), 3, 1000);
+        assert_eq!(c.batch_size(), 1000);
+        assert_eq!(c.wait(), Duration::from_millis(20));
+    }
+}
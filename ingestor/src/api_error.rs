@@ -0,0 +1,330 @@
+use crate::auth::AuthError;
+use crate::errors::Error;
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        FromRequest, FromRequestParts, Request,
+    },
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::QueryRejection;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied request id that is passed through unchanged
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    /// Id of the request being handled, set by `request_id`
+    static REQUEST_ID: String;
+}
+
+/// An error returned to REST clients as a JSON body.
+///
+/// `message` and `details` are shown to the client; the underlying cause of a
+/// 5xx is only logged, together with the request id.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<Value>,
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = ApiError)]
+pub struct ErrorBody<'a> {
+    /// Stable machine-readable error code
+    #[schema(example = "invalid_time_range")]
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    details: Option<&'a Value>,
+    /// Also returned in the `X-Request-Id` header
+    request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    /// A 500 whose cause is logged but not returned
+    pub(crate) fn internal(cause: &dyn std::fmt::Display) -> Self {
+        error!(
+            "Internal error (request {}): {}",
+            current_request_id().as_deref().unwrap_or("-"),
+            cause
+        );
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "Internal server error",
+        )
+    }
+
+    /// A 503 whose cause is logged but not returned
+    fn unavailable(message: &str, cause: &dyn std::fmt::Display) -> Self {
+        warn!(
+            "Service unavailable (request {}): {}",
+            current_request_id().as_deref().unwrap_or("-"),
+            cause
+        );
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "unavailable", message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            code: self.code,
+            message: &self.message,
+            details: self.details.as_ref(),
+            request_id: current_request_id(),
+        };
+        let mut response = (self.status, Json(body)).into_response();
+
+        let headers = response.headers_mut();
+        match self.status {
+            StatusCode::UNAUTHORIZED => {
+                headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                headers.insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Validation(message) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "validation_failed", message)
+            }
+            Error::Json(e) => Self::bad_request("invalid_json", "Request body is not valid JSON")
+                .with_details(json!({ "reason": e.to_string() })),
+            Error::RateLimited(message) => {
+                Self::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", message)
+            }
+            Error::ChannelSend => Self::unavailable("Ingest pipeline unavailable", &err),
+            Error::Database(e) => Self::from(e),
+            Error::Mqtt(_) | Error::Migration(_) | Error::Kafka(_) | Error::Io(_) | Error::Config(_) => {
+                Self::internal(&err)
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::RowNotFound => Self::not_found("Resource not found"),
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                Self::unavailable("Database unavailable", &err)
+            }
+            sqlx::Error::Database(db_err) => match db_err.code().as_deref() {
+                // Connection problems, admin shutdown, too many connections, statement timeout
+                Some(code)
+                    if code.starts_with("08")
+                        || code.starts_with("57P")
+                        || code == "53300"
+                        || code == "57014" =>
+                {
+                    Self::unavailable("Database unavailable", &err)
+                }
+                // Data exceptions, e.g. a timestamp out of range
+                Some(code) if code.starts_with("22") => {
+                    Self::bad_request("invalid_parameter", "A parameter is out of range")
+                }
+                _ => Self::internal(&err),
+            },
+            _ => Self::internal(&err),
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::Unauthorized(message) => {
+                Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+            }
+            AuthError::Forbidden(message) => Self::new(StatusCode::FORBIDDEN, "forbidden", message),
+            AuthError::Unavailable => Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "unavailable",
+                "Credentials could not be verified",
+            ),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::bad_request("invalid_query", "Invalid query parameters")
+            .with_details(json!({ "reason": rejection.to_string() }))
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::bad_request("invalid_path", "Invalid path parameter")
+            .with_details(json!({ "reason": rejection.body_text() }))
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        let (code, message) = match &rejection {
+            JsonRejection::JsonDataError(_) => ("invalid_body", "Request body has invalid fields"),
+            JsonRejection::MissingJsonContentType(_) => {
+                ("unsupported_media_type", "Expected Content-Type: application/json")
+            }
+            _ => ("invalid_json", "Request body is not valid JSON"),
+        };
+        Self::new(rejection.status(), code, message)
+            .with_details(json!({ "reason": rejection.body_text() }))
+    }
+}
+
+/// `Query` that rejects with an `ApiError`; repeated keys deserialize into a `Vec`
+#[derive(FromRequestParts)]
+#[from_request(via(axum_extra::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
+
+/// `Path` that rejects with an `ApiError`
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct ApiPath<T>(pub T);
+
+/// `Json` body that rejects with an `ApiError`
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// Fallback for unknown routes
+pub async fn not_found() -> ApiError {
+    ApiError::not_found("No such endpoint")
+}
+
+fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Assigns each request an id, echoed in `X-Request-Id` and in error bodies.
+///
+/// A well-formed id sent by the client (e.g. from a proxy) is reused.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| {
+            !v.is_empty()
+                && v.len() <= MAX_REQUEST_ID_LEN
+                && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_json(error: ApiError) -> (StatusCode, Value) {
+        let response = REQUEST_ID
+            .scope("req-1".to_string(), async { error.into_response() })
+            .await;
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn test_error_mapping() {
+        let cases = [
+            (Error::Validation("bad".to_string()), StatusCode::UNPROCESSABLE_ENTITY),
+            (Error::RateLimited("slow down".to_string()), StatusCode::TOO_MANY_REQUESTS),
+            (Error::ChannelSend, StatusCode::SERVICE_UNAVAILABLE),
+            (Error::Database(sqlx::Error::RowNotFound), StatusCode::NOT_FOUND),
+            (Error::Database(sqlx::Error::PoolTimedOut), StatusCode::SERVICE_UNAVAILABLE),
+            (
+                Error::Io(std::io::Error::other("disk")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (err, status) in cases {
+            assert_eq!(ApiError::from(err).status, status);
+        }
+    }
+
+    #[test]
+    fn test_internal_details_not_leaked() {
+        tokio_test::block_on(async {
+            let err = ApiError::from(Error::Database(sqlx::Error::Protocol(
+                "relation \"telemetry\" does not exist".to_string(),
+            )));
+            let (status, body) = body_json(err).await;
+
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(body["code"], "internal");
+            assert_eq!(body["message"], "Internal server error");
+            assert_eq!(body["request_id"], "req-1");
+            assert!(body.get("details").is_none());
+        });
+    }
+
+    #[test]
+    fn test_details_and_auth() {
+        tokio_test::block_on(async {
+            let err = ApiError::bad_request("invalid_time_range", "start is after end")
+                .with_details(json!({ "start": "b", "end": "a" }));
+            let (status, body) = body_json(err).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["details"]["start"], "b");
+
+            let err = ApiError::from(AuthError::Forbidden("Missing required scope admin".into()));
+            assert_eq!(err.status, StatusCode::FORBIDDEN);
+            assert_eq!(err.code, "forbidden");
+        });
+    }
+}
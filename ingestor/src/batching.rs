@@ -1,110 +1,359 @@
-use crate::db::insert_batch;
-use crate::metrics::{BATCH_SIZE, INGEST_LATENCY_SECONDS};
-use crate::model::Telemetry;
-use sqlx::PgPool;
+use crate::adaptive::{AdaptiveConfig, AdaptiveController};
+use crate::dedup::DedupWindow;
+use crate::lanes::{LaneDepth, LaneReceivers};
+use crate::metrics::{
+    ADAPTIVE_BATCH_SIZE, ADAPTIVE_FLUSH_INTERVAL_SECONDS, DUPLICATES_TOTAL,
+    INGEST_LATENCY_SECONDS, ROWS_DISCARDED_TOTAL, ROWS_INSERTED_TOTAL, ROWS_SENT_TOTAL,
+    SINK_FAILURES_TOTAL, SINK_ROWS_TOTAL, SINK_WRITE_LATENCY_SECONDS, WORKER_BATCH_SIZE, WORKER_INGEST_LATENCY_SECONDS,
+    WORKER_QUEUE_DEPTH, WORKER_ROWS_TOTAL,
+};
+use crate::model::Envelope;
+use crate::sink::TelemetrySink;
+use futures_util::future::join_all;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio::time::{interval, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant};
 use tracing::{debug, error, info};
 
+/// Batch writer settings
+#[derive(Debug, Clone)]
+pub struct BatcherConfig {
+    pub workers: usize,
+    pub max_batch: usize,
+    pub max_wait_ms: u64,
+    /// How long a reading is remembered to drop redeliveries; zero disables dedup
+    pub dedup_window_secs: u64,
+    /// Upper bound on readings remembered per worker
+    pub dedup_max_entries: usize,
+    /// When set, batch size and flush interval are tuned at runtime within these bounds
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+/// Fans telemetry out to `config.workers` parallel batch writers.
+///
+/// Messages are sharded by a hash of `device_id`, so every reading of a given
+/// device goes through the same worker and is inserted in the order it left
+/// the lanes. Lanes are drained in priority order, so high-priority readings
+/// overtake routine ones still waiting, including those of the same device.
+///
+/// Every batch is written to each of `sinks`; the first is the primary, which
+/// the row accounting metrics follow.
 pub async fn run_batcher(
-    mut rx: mpsc::Receiver<Telemetry>,
-    pool: PgPool,
-    max_batch: usize,
-    max_wait_ms: u64,
+    mut rx: LaneReceivers,
+    sinks: Vec<Arc<dyn TelemetrySink>>,
+    config: BatcherConfig,
 ) {
+    let workers = config.workers.max(1);
     info!(
-        "Starting batcher with max_batch={}, max_wait_ms={}",
-        max_batch, max_wait_ms
+        "Starting batcher with workers={}, max_batch={}, max_wait_ms={}, sinks={:?}, dedup_window_secs={}, adaptive={:?}",
+        workers,
+        config.max_batch,
+        config.max_wait_ms,
+        sinks.iter().map(|s| s.name()).collect::<Vec<_>>(),
+        config.dedup_window_secs,
+        config.adaptive
     );
 
-    let mut buffer: Vec<Telemetry> = Vec::with_capacity(max_batch);
-    let mut ticker = interval(Duration::from_millis(max_wait_ms));
+    let mut senders = Vec::with_capacity(workers);
+    let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(workers);
+
+    for worker_id in 0..workers {
+        let (worker_tx, worker_rx) = mpsc::channel(config.max_batch.max(1));
+        let worker_sinks = sinks.clone();
+        let worker_config = config.clone();
+        let lanes = rx.depth();
+        senders.push(worker_tx);
+        handles.push(tokio::spawn(async move {
+            run_worker(worker_id, worker_rx, lanes, worker_sinks, worker_config).await;
+        }));
+    }
+
+    while let Some(t) = rx.recv().await {
+        let worker_id = shard_for(&t.telemetry.device_id, workers);
+        let worker_tx = &senders[worker_id];
+        if worker_tx.send(t).await.is_err() {
+            error!("Batch worker {} stopped, dropping record", worker_id);
+            continue;
+        }
+        WORKER_QUEUE_DEPTH
+            .with_label_values(&[&worker_id.to_string()])
+            .set((worker_tx.max_capacity() - worker_tx.capacity()) as f64);
+    }
+
+    // Channel closed: close worker queues so each flushes what it holds
+    info!("Channel closed, waiting for batch workers to flush");
+    drop(senders);
+    for handle in handles {
+        if let Err(e) = handle.await {
+            error!("Batch worker panicked: {}", e);
+        }
+    }
+    for sink in &sinks {
+        if let Err(e) = sink.close().await {
+            error!("Failed to close {} sink: {}", sink.name(), e);
+        }
+    }
+
+    info!("Batcher stopped");
+}
+
+/// Picks the worker responsible for a device.
+fn shard_for(device_id: &str, workers: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    device_id.hash(&mut hasher);
+    (hasher.finish() % workers as u64) as usize
+}
+
+async fn run_worker(
+    worker_id: usize,
+    mut rx: mpsc::Receiver<Envelope>,
+    lanes: LaneDepth,
+    sinks: Vec<Arc<dyn TelemetrySink>>,
+    config: BatcherConfig,
+) {
+    debug!("Batch worker {} started", worker_id);
+    let workers = config.workers.max(1);
+
+    let label = worker_id.to_string();
+    let mut dedup = (config.dedup_window_secs > 0).then(|| {
+        DedupWindow::new(
+            Duration::from_secs(config.dedup_window_secs),
+            config.dedup_max_entries,
+        )
+    });
+    let mut controller = config
+        .adaptive
+        .map(|a| AdaptiveController::new(a, config.max_batch, config.max_wait_ms));
+    let mut batch_limit = config.max_batch;
+    let mut wait = Duration::from_millis(config.max_wait_ms);
+    if let Some(c) = &controller {
+        batch_limit = c.batch_size();
+        wait = c.wait();
+        publish_adaptive(&label, c);
+    }
+
+    let mut buffer: Vec<Envelope> = Vec::with_capacity(config.max_batch);
+    let mut ticker = interval_at(Instant::now() + wait, wait);
 
     loop {
-        tokio::select! {
+        let (flush_due, closed) = tokio::select! {
             // Receive telemetry data
             telemetry = rx.recv() => {
                 match telemetry {
+                    Some(t) if dedup.as_mut().is_some_and(|d| d.check(&t)) => {
+                        // QoS 1 redelivery of a reading we already have
+                        DUPLICATES_TOTAL.inc();
+                        (false, false)
+                    }
                     Some(t) => {
                         buffer.push(t);
-
                         // Flush if buffer is full
-                        if buffer.len() >= max_batch {
-                            flush_batch(&pool, &mut buffer).await;
-                        }
-                    }
-                    None => {
-                        // Channel closed, flush remaining and exit
-                        info!("Channel closed, flushing remaining batch");
-                        flush_batch(&pool, &mut buffer).await;
-                        break;
+                        (buffer.len() >= batch_limit, false)
                     }
+                    // Channel closed, flush remaining and exit
+                    None => (true, true),
                 }
             }
 
             // Periodic flush timer
-            _ = ticker.tick() => {
-                if !buffer.is_empty() {
-                    flush_batch(&pool, &mut buffer).await;
+            _ = ticker.tick() => (!buffer.is_empty(), false),
+        };
+
+        if flush_due {
+            let elapsed = flush_batch(&sinks, &label, &mut buffer).await;
+
+            if let (Some(c), Some(elapsed)) = (controller.as_mut(), elapsed) {
+                // Backlog is measured on the ingest lanes, shared evenly by the workers
+                c.observe(elapsed, lanes.depth() / workers, lanes.capacity() / workers);
+                batch_limit = c.batch_size();
+                if c.wait() != wait {
+                    wait = c.wait();
+                    ticker = interval_at(Instant::now() + wait, wait);
                 }
+                publish_adaptive(&label, c);
             }
         }
+
+        if closed {
+            break;
+        }
     }
 
-    info!("Batcher stopped");
+    debug!("Batch worker {} stopped", worker_id);
+}
+
+fn publish_adaptive(worker: &str, controller: &AdaptiveController) {
+    ADAPTIVE_BATCH_SIZE
+        .with_label_values(&[worker])
+        .set(controller.batch_size() as f64);
+    ADAPTIVE_FLUSH_INTERVAL_SECONDS
+        .with_label_values(&[worker])
+        .set(controller.wait().as_secs_f64());
 }
 
-async fn flush_batch(pool: &PgPool, buffer: &mut Vec<Telemetry>) {
+/// Writes the buffer to every sink and returns how long the primary (first)
+/// sink took, or `None` if it was empty. The sinks are written concurrently,
+/// so a slow secondary sink does not show up in the primary's latency.
+async fn flush_batch(
+    sinks: &[Arc<dyn TelemetrySink>],
+    worker: &str,
+    buffer: &mut Vec<Envelope>,
+) -> Option<Duration> {
     let batch_len = buffer.len();
     if batch_len == 0 {
-        return;
+        return None;
     }
 
-    debug!("Flushing batch of {} records", batch_len);
-    BATCH_SIZE.set(batch_len as f64);
+    debug!("Worker {} flushing batch of {} records", worker, batch_len);
+    WORKER_BATCH_SIZE
+        .with_label_values(&[worker])
+        .set(batch_len as f64);
+
+    // Sinks are retried independently, so one failing does not rewrite the others
+    let results = join_all(sinks.iter().map(|sink| async {
+        let start = Instant::now();
+        let rows = write_with_retry(sink.as_ref(), buffer).await;
+        let elapsed = start.elapsed();
+        SINK_WRITE_LATENCY_SECONDS
+            .with_label_values(&[sink.name()])
+            .observe(elapsed.as_secs_f64());
+        (rows, elapsed)
+    }))
+    .await;
 
-    let start = Instant::now();
+    let (primary_rows, elapsed) = results.first().copied().unwrap_or_default();
+    let secs = elapsed.as_secs_f64();
+    INGEST_LATENCY_SECONDS.observe(secs);
+    WORKER_INGEST_LATENCY_SECONDS
+        .with_label_values(&[worker])
+        .observe(secs);
+    if let Some(rows) = primary_rows {
+        WORKER_ROWS_TOTAL
+            .with_label_values(&[worker])
+            .inc_by(batch_len as f64);
+        ROWS_SENT_TOTAL.inc_by(batch_len as f64);
+        ROWS_INSERTED_TOTAL.inc_by(rows as f64);
+        ROWS_DISCARDED_TOTAL.inc_by((batch_len as u64).saturating_sub(rows) as f64);
+        debug!("Batch written in {:.3}s", secs);
+    }
+
+    // Cleared even on failure to prevent blocking
+    buffer.clear();
+    WORKER_BATCH_SIZE.with_label_values(&[worker]).set(0.0);
+    Some(elapsed)
+}
 
-    // Retry logic: 3 attempts with exponential backoff
+/// Writes a batch with up to 3 attempts; `None` if it was dropped
+async fn write_with_retry(sink: &dyn TelemetrySink, batch: &[Envelope]) -> Option<u64> {
     const MAX_RETRIES: u32 = 3;
     let mut attempt = 0;
 
     loop {
         attempt += 1;
 
-        match insert_batch(pool, buffer).await {
-            Ok(()) => {
-                let elapsed = start.elapsed().as_secs_f64();
-                INGEST_LATENCY_SECONDS.observe(elapsed);
+        match sink.write(batch).await {
+            Ok(rows) => {
+                SINK_ROWS_TOTAL.with_label_values(&[sink.name()]).inc_by(rows as f64);
                 if attempt > 1 {
-                    info!("Batch inserted successfully after {} attempts in {:.3}s", attempt, elapsed);
-                } else {
-                    debug!("Batch inserted successfully in {:.3}s", elapsed);
+                    info!("Batch written to {} after {} attempts", sink.name(), attempt);
                 }
-                // Only clear buffer on success
-                buffer.clear();
-                BATCH_SIZE.set(0.0);
-                return;
+                return Some(rows);
             }
             Err(e) => {
+                SINK_FAILURES_TOTAL.with_label_values(&[sink.name()]).inc();
                 if attempt >= MAX_RETRIES {
                     // Final failure after all retries
-                    error!("Failed to insert batch after {} attempts: {}", MAX_RETRIES, e);
-                    error!("CRITICAL: {} records will be dropped due to persistent DB failure", batch_len);
-                    // Clear buffer to prevent blocking
-                    buffer.clear();
-                    BATCH_SIZE.set(0.0);
-                    return;
+                    error!("Failed to write batch to {} after {} attempts: {}", sink.name(), MAX_RETRIES, e);
+                    error!(
+                        "CRITICAL: {} records will be dropped from {} due to persistent failure",
+                        batch.len(),
+                        sink.name()
+                    );
+                    return None;
                 }
 
                 // Retry with exponential backoff: 100ms, 200ms, 400ms
                 let backoff_ms = 100 * 2_u64.pow(attempt - 1);
-                error!("Failed to insert batch (attempt {}/{}): {}. Retrying in {}ms...", 
-                       attempt, MAX_RETRIES, e, backoff_ms);
-                
+                error!("Failed to write batch to {} (attempt {}/{}): {}. Retrying in {}ms...",
+                       sink.name(), attempt, MAX_RETRIES, e, backoff_ms);
+
                 tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::Result;
+    use crate::model::Telemetry;
+    use chrono::Utc;
+    use futures_util::future::BoxFuture;
+
+    /// Stores every batch after a fixed delay
+    struct SlowSink {
+        name: &'static str,
+        delay: Duration,
+    }
+
+    impl TelemetrySink for SlowSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>> {
+            Box::pin(async move {
+                tokio::time::sleep(self.delay).await;
+                Ok(batch.len() as u64)
+            })
+        }
+    }
+
+    #[test]
+    fn test_shard_is_stable() {
+        let first = shard_for("dev-42", 8);
+        for _ in 0..100 {
+            assert_eq!(shard_for("dev-42", 8), first);
+        }
+    }
+
+    #[test]
+    fn test_shard_in_range() {
+        for i in 0..1000 {
+            let device_id = format!("dev-{}", i);
+            assert!(shard_for(&device_id, 4) < 4);
+        }
+        assert_eq!(shard_for("dev-1", 1), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_reports_primary_latency() {
+        let sinks: Vec<Arc<dyn TelemetrySink>> = vec![
+            Arc::new(SlowSink {
+                name: "test-primary",
+                delay: Duration::from_millis(10),
+            }),
+            Arc::new(SlowSink {
+                name: "test-secondary",
+                delay: Duration::from_secs(2),
+            }),
+        ];
+        let mut buffer = vec![Telemetry::new("dev-1", Utc::now(), 20.0, 50.0, 90.0).into()];
+
+        let elapsed = flush_batch(&sinks, "test", &mut buffer).await.unwrap();
+        assert_eq!(elapsed, Duration::from_millis(10));
+        assert!(buffer.is_empty());
+        for (sink, secs) in [("test-primary", 0.01), ("test-secondary", 2.0)] {
+            let latency = SINK_WRITE_LATENCY_SECONDS.with_label_values(&[sink]);
+            assert_eq!(latency.get_sample_count(), 1);
+            assert_eq!(latency.get_sample_sum(), secs);
+        }
+
+        assert_eq!(flush_batch(&sinks, "test", &mut buffer).await, None);
+    }
+}
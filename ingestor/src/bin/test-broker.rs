@@ -0,0 +1,40 @@
+//! MQTT broker for tests: rumqttd on `127.0.0.1:<port>`, run as a child
+//! process by `ingestor::harness::TestBroker`. rumqttd has no shutdown, so a
+//! process of its own is what lets the harness stop it.
+
+use rumqttd::{Broker, Config, ConnectionSettings, RouterConfig, ServerSettings};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+fn main() {
+    let port: u16 = std::env::args()
+        .nth(1)
+        .and_then(|port| port.parse().ok())
+        .expect("usage: test-broker <port>");
+    let server = ServerSettings {
+        name: format!("test-broker-{}", port),
+        listen: SocketAddr::from(([127, 0, 0, 1], port)),
+        tls: None,
+        next_connection_delay_ms: 0,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 5000,
+            max_payload_size: 1 << 20,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+    let config = Config {
+        router: RouterConfig {
+            max_connections: 100,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1 << 20,
+            max_segment_count: 10,
+            ..RouterConfig::default()
+        },
+        v4: Some(HashMap::from([("v4".to_string(), server)])),
+        ..Config::default()
+    };
+    Broker::new(config).start().expect("MQTT broker failed");
+}
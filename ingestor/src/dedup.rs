@@ -0,0 +1,128 @@
+use crate::model::Envelope;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Remembers recently seen readings so MQTT QoS 1 redeliveries can be dropped.
+///
+/// A reading is a duplicate only if tenant, device, timestamp and every value
+/// match; a corrected reading with the same timestamp is let through so the
+/// conflict policy can decide what to do with it.
+#[derive(Debug)]
+pub struct DedupWindow {
+    ttl: Duration,
+    max_entries: usize,
+    seen: HashMap<u64, Instant>,
+    order: VecDeque<(u64, Instant)>,
+}
+
+impl DedupWindow {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries: max_entries.max(1),
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if the reading was already seen within the window
+    pub fn check(&mut self, envelope: &Envelope) -> bool {
+        self.check_at(envelope, Instant::now())
+    }
+
+    fn check_at(&mut self, envelope: &Envelope, now: Instant) -> bool {
+        self.evict(now);
+
+        let key = fingerprint(envelope);
+        if self.seen.contains_key(&key) {
+            return true;
+        }
+
+        self.seen.insert(key, now);
+        self.order.push_back((key, now));
+        false
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some(&(key, seen_at)) = self.order.front() {
+            let expired = now.duration_since(seen_at) > self.ttl;
+            if !expired && self.order.len() < self.max_entries {
+                break;
+            }
+            self.order.pop_front();
+            // Only remove if the map still points at this entry
+            if self.seen.get(&key) == Some(&seen_at) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+fn fingerprint(envelope: &Envelope) -> u64 {
+    let t = &envelope.telemetry;
+    let mut hasher = DefaultHasher::new();
+    envelope.tenant_id.hash(&mut hasher);
+    t.device_id.hash(&mut hasher);
+    t.timestamp.timestamp_micros().hash(&mut hasher);
+    t.temperature.to_bits().hash(&mut hasher);
+    t.humidity.to_bits().hash(&mut hasher);
+    t.battery.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Telemetry;
+    use chrono::Utc;
+
+    fn sample(temperature: f64) -> Envelope {
+        Telemetry::new("dev-1", Utc::now(), temperature, 60.0, 80.0).into()
+    }
+
+    #[test]
+    fn test_redelivery_is_duplicate() {
+        let mut window = DedupWindow::new(Duration::from_secs(60), 100);
+        let t = sample(25.0);
+
+        assert!(!window.check(&t));
+        assert!(window.check(&t.clone()));
+    }
+
+    #[test]
+    fn test_correction_is_not_duplicate() {
+        let mut window = DedupWindow::new(Duration::from_secs(60), 100);
+        let original = sample(25.0);
+        let mut corrected = original.clone();
+        corrected.telemetry.temperature = 26.0;
+
+        assert!(!window.check(&original));
+        assert!(!window.check(&corrected));
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let mut window = DedupWindow::new(Duration::from_secs(60), 100);
+        let t = sample(25.0);
+        let now = Instant::now();
+
+        assert!(!window.check_at(&t, now));
+        assert!(!window.check_at(&t, now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_capacity_bound() {
+        let mut window = DedupWindow::new(Duration::from_secs(60), 2);
+        let a = sample(1.0);
+        let b = sample(2.0);
+        let c = sample(3.0);
+
+        assert!(!window.check(&a));
+        assert!(!window.check(&b));
+        assert!(!window.check(&c));
+        // Oldest entry was pushed out
+        assert!(!window.check(&a));
+    }
+}
@@ -14,6 +14,15 @@ pub enum Error {
     #[error("Validation error: {0}")]
     Validation(String),
 
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("Kafka error: {0}")]
+    Kafka(String),
+
     #[error("JSON parsing error: {0}")]
     Json(#[from] serde_json::Error),
 
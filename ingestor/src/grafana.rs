@@ -0,0 +1,388 @@
+use crate::api_error::ApiError;
+use crate::model::DeviceSeries;
+use crate::query::{self, Episode, Series, SeriesQuery, SeriesRow, TelemetryFilter, TelemetryQuery, MEASUREMENTS};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+/// Most device ids returned by a `devices` search
+const MAX_SEARCH_DEVICES: i64 = 1000;
+
+/// Most annotations returned for one annotation query
+const MAX_ANNOTATIONS: usize = 1000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = GrafanaRange)]
+pub struct TimeRange {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[schema(as = GrafanaSearchRequest)]
+pub struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = GrafanaQueryRequest)]
+pub struct QueryRequest {
+    range: TimeRange,
+    max_data_points: Option<usize>,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = GrafanaTarget)]
+pub struct Target {
+    #[serde(default)]
+    target: String,
+    #[serde(default, rename = "type")]
+    kind: TargetKind,
+    /// Filters, in the form of `/api/v1/telemetry` query parameters
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    payload: Value,
+    #[serde(default)]
+    hide: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[schema(as = GrafanaTargetKind)]
+pub enum TargetKind {
+    #[default]
+    Timeserie,
+    Table,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+#[schema(as = GrafanaQueryResult)]
+pub enum QueryResult {
+    TimeSeries {
+        /// `<device_id> <field>`
+        target: String,
+        /// `[value, unix milliseconds]`
+        #[schema(value_type = Vec<Vec<Option<f64>>>)]
+        datapoints: Vec<(Option<f64>, i64)>,
+    },
+    Table {
+        /// Always `table`
+        #[serde(rename = "type")]
+        kind: &'static str,
+        #[schema(value_type = Vec<Object>)]
+        columns: Vec<Value>,
+        #[schema(value_type = Vec<Vec<Value>>)]
+        rows: Vec<Value>,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = GrafanaAnnotationRequest)]
+pub struct AnnotationRequest {
+    range: TimeRange,
+    annotation: AnnotationQuery,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = GrafanaAnnotationQuery)]
+pub struct AnnotationQuery {
+    #[serde(default)]
+    name: String,
+    /// Value predicates in query string form, e.g. `battery_lt=20&device_id=pump-*`
+    #[serde(default)]
+    query: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = GrafanaAnnotation)]
+pub struct AnnotationEvent {
+    /// Episode start, unix milliseconds
+    pub time: i64,
+    /// Episode end, unix milliseconds
+    pub time_end: i64,
+    pub title: String,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+/// Measurements matching the search text; `devices` or `devices:<prefix>`
+/// lists device ids instead, e.g. for a dashboard variable
+pub async fn search(
+    pool: &PgPool,
+    tenant: Option<&String>,
+    request: &SearchRequest,
+) -> Result<Vec<String>, ApiError> {
+    let text = request.target.trim();
+    if text == "devices" || text.starts_with("devices:") {
+        let prefix = text.strip_prefix("devices").unwrap_or("").trim_start_matches(':');
+        let query_string = serde_html_form::to_string([("device_prefix", prefix)]).unwrap_or_default();
+        let params: TelemetryQuery = parse(&query_string, text)?;
+        let filter = TelemetryFilter::new(&params, tenant)?;
+        let mut query = sqlx::QueryBuilder::new("SELECT DISTINCT device_id FROM telemetry");
+        filter.push_where(&mut query);
+        query
+            .push(" ORDER BY device_id LIMIT ")
+            .push_bind(MAX_SEARCH_DEVICES);
+        return Ok(query.build_query_scalar().fetch_all(pool).await?);
+    }
+    Ok(MEASUREMENTS
+        .iter()
+        .filter(|m| m.contains(text))
+        .map(|m| m.to_string())
+        .collect())
+}
+
+/// Grafana JSON datasource `/query`: one result per device for each visible target.
+///
+/// A target is either a measurement name picked from `/search`, with filters
+/// in its JSON payload, or a query string in the syntax of the series endpoint
+/// such as `field=battery&device_id=pump-*&agg=min`. The dashboard's time
+/// range and `maxDataPoints` always win over the target.
+pub async fn query(
+    pool: &PgPool,
+    tenant: Option<&String>,
+    request: &QueryRequest,
+) -> Result<Vec<QueryResult>, ApiError> {
+    let mut results = Vec::new();
+    for target in request.targets.iter().filter(|t| !t.hide && !t.target.trim().is_empty()) {
+        let query_string = target_query(target, &request.range, request.max_data_points)?;
+        let params: TelemetryQuery = parse(&query_string, &target.target)?;
+        let series_params: SeriesQuery = parse(&query_string, &target.target)?;
+        let mut filter = TelemetryFilter::new(&params, tenant)?;
+        let series = Series::new(&series_params, &mut filter)?;
+
+        let rows: Vec<SeriesRow> = series
+            .select(&filter)
+            .build_query_as()
+            .fetch_all(pool)
+            .await?;
+        let device_series = query::group_rows(rows);
+
+        match target.kind {
+            TargetKind::Timeserie => {
+                results.extend(device_series.into_iter().map(|s| QueryResult::TimeSeries {
+                    target: format!("{} {}", s.device_id, series.field),
+                    datapoints: s
+                        .points
+                        .iter()
+                        .map(|p| (p.value, p.timestamp.timestamp_millis()))
+                        .collect(),
+                }));
+            }
+            TargetKind::Table => results.push(table(series.field, device_series)),
+        }
+    }
+    Ok(results)
+}
+
+fn table(field: &str, device_series: Vec<DeviceSeries>) -> QueryResult {
+    QueryResult::Table {
+        kind: "table",
+        columns: vec![
+            json!({ "text": "Time", "type": "time" }),
+            json!({ "text": "device_id", "type": "string" }),
+            json!({ "text": field, "type": "number" }),
+        ],
+        rows: device_series
+            .iter()
+            .flat_map(|s| {
+                s.points
+                    .iter()
+                    .map(move |p| json!([p.timestamp.timestamp_millis(), s.device_id, p.value]))
+            })
+            .collect(),
+    }
+}
+
+/// Alert episodes: periods in which a device's readings matched the
+/// annotation's value predicates
+pub async fn annotations(
+    pool: &PgPool,
+    tenant: Option<&String>,
+    request: &AnnotationRequest,
+) -> Result<Vec<AnnotationEvent>, ApiError> {
+    let annotation = &request.annotation;
+    let query_string = with_range(parse_pairs(&annotation.query)?, &request.range);
+    let params: TelemetryQuery = parse(&query_string, &annotation.query)?;
+    let filter = TelemetryFilter::new(&params, tenant)?;
+
+    let episodes: Vec<Episode> = filter
+        .episodes(MAX_ANNOTATIONS)?
+        .build_query_as()
+        .fetch_all(pool)
+        .await?;
+
+    let title = if annotation.name.is_empty() {
+        annotation.query.clone()
+    } else {
+        annotation.name.clone()
+    };
+    Ok(episodes
+        .into_iter()
+        .map(|episode| AnnotationEvent {
+            time: episode.started.timestamp_millis(),
+            time_end: episode.ended.timestamp_millis(),
+            title: title.clone(),
+            text: format!(
+                "{}: {} ({} readings)",
+                episode.device_id, annotation.query, episode.readings
+            ),
+            tags: vec!["alert".to_string(), episode.device_id],
+        })
+        .collect())
+}
+
+/// The target as a series query string, with the dashboard's range and resolution
+fn target_query(
+    target: &Target,
+    range: &TimeRange,
+    max_data_points: Option<usize>,
+) -> Result<String, ApiError> {
+    let text = target.target.trim();
+    let mut pairs = if text.contains('=') {
+        parse_pairs(text)?
+    } else {
+        vec![("field".to_string(), text.to_string())]
+    };
+    if let Value::Object(payload) = &target.payload {
+        for (key, value) in payload {
+            let values = match value {
+                Value::Array(values) => values.clone(),
+                value => vec![value.clone()],
+            };
+            for value in values {
+                match value {
+                    Value::String(s) => pairs.push((key.clone(), s)),
+                    Value::Number(_) | Value::Bool(_) => pairs.push((key.clone(), value.to_string())),
+                    _ => {}
+                }
+            }
+        }
+    }
+    if let Some(points) = max_data_points {
+        pairs.retain(|(key, _)| key != "points");
+        pairs.push(("points".to_string(), points.to_string()));
+    }
+    Ok(with_range(pairs, range))
+}
+
+fn with_range(mut pairs: Vec<(String, String)>, range: &TimeRange) -> String {
+    pairs.retain(|(key, _)| key != "start" && key != "end");
+    pairs.push((
+        "start".to_string(),
+        range.from.to_rfc3339_opts(SecondsFormat::Millis, true),
+    ));
+    pairs.push((
+        "end".to_string(),
+        range.to.to_rfc3339_opts(SecondsFormat::Millis, true),
+    ));
+    serde_html_form::to_string(&pairs).unwrap_or_default()
+}
+
+fn parse_pairs(text: &str) -> Result<Vec<(String, String)>, ApiError> {
+    parse(text.trim().trim_start_matches('?'), text)
+}
+
+fn parse<T: DeserializeOwned>(query_string: &str, target: &str) -> Result<T, ApiError> {
+    serde_html_form::from_str(query_string).map_err(|e| {
+        ApiError::bad_request("invalid_target", "Invalid Grafana query target")
+            .with_details(json!({ "target": target, "reason": e.to_string() }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range() -> TimeRange {
+        TimeRange {
+            from: "2025-10-05T00:00:00Z".parse().unwrap(),
+            to: "2025-10-05T06:00:00Z".parse().unwrap(),
+        }
+    }
+
+    fn target(value: Value) -> Target {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_target_query() {
+        // Metric name plus payload
+        let query_string = target_query(
+            &target(json!({
+                "target": "battery",
+                "refId": "A",
+                "payload": { "device_id": ["a", "b"], "agg": "min", "battery_lt": 20 }
+            })),
+            &range(),
+            Some(300),
+        )
+        .unwrap();
+        assert_eq!(
+            query_string,
+            "field=battery&agg=min&battery_lt=20&device_id=a&device_id=b&points=300\
+             &start=2025-10-05T00%3A00%3A00.000Z&end=2025-10-05T06%3A00%3A00.000Z"
+        );
+
+        // Query string target; the dashboard range replaces the target's
+        let query_string = target_query(
+            &target(json!({ "target": "field=temperature&device_id=pump-*&start=2020-01-01T00:00:00Z" })),
+            &range(),
+            None,
+        )
+        .unwrap();
+        let params: SeriesQuery = parse(&query_string, "").unwrap();
+        assert_eq!(params.field, "temperature");
+        assert!(query_string.contains("device_id=pump-*"));
+        assert!(!query_string.contains("2020"));
+    }
+
+    #[test]
+    fn test_request_formats() {
+        let request: QueryRequest = serde_json::from_value(json!({
+            "range": { "from": "2025-10-05T00:00:00.000Z", "to": "2025-10-05T06:00:00.000Z", "raw": {} },
+            "interval": "30s",
+            "intervalMs": 30000,
+            "maxDataPoints": 550,
+            "targets": [
+                { "target": "temperature", "refId": "A", "type": "timeserie" },
+                { "target": "battery", "refId": "B", "type": "table", "hide": true }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(request.targets[1].kind, TargetKind::Table);
+
+        let result = serde_json::to_value(QueryResult::TimeSeries {
+            target: "dev-1 temperature".to_string(),
+            datapoints: vec![(Some(21.5), 1_000), (None, 2_000)],
+        })
+        .unwrap();
+        assert_eq!(result["datapoints"], json!([[21.5, 1000], [null, 2000]]));
+
+        let event = serde_json::to_value(AnnotationEvent {
+            time: 1,
+            time_end: 2,
+            title: "Low battery".to_string(),
+            text: "dev-1".to_string(),
+            tags: vec!["alert".to_string()],
+        })
+        .unwrap();
+        assert_eq!(event["timeEnd"], 2);
+    }
+
+    #[test]
+    fn test_invalid_target() {
+        let query_string = target_query(&target(json!({ "target": "pressure" })), &range(), None).unwrap();
+        let params: SeriesQuery = parse(&query_string, "pressure").unwrap();
+        let mut filter = TelemetryFilter::new(&parse(&query_string, "").unwrap(), None).unwrap();
+        assert!(Series::new(&params, &mut filter).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use sqlx::{Connection, PgConnection, PgPool};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// MQTT broker for tests: the `test-broker` binary (rumqttd) listening on a
+/// random local port, killed on drop. Integration tests pass
+/// `env!("CARGO_BIN_EXE_test-broker")` as `program`.
+pub struct TestBroker {
+    pub port: u16,
+    broker: Child,
+}
+
+impl TestBroker {
+    pub async fn start(program: impl AsRef<Path>) -> Self {
+        // rumqttd binds the port itself, so borrow a free one from the OS
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let broker = Command::new(program.as_ref())
+            .arg(port.to_string())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("cannot run {}: {}", program.as_ref().display(), e));
+        let mut broker = Self { port, broker };
+
+        let listen = SocketAddr::from(([127, 0, 0, 1], port));
+        for _ in 0..500 {
+            if TcpStream::connect(listen).await.is_ok() {
+                return broker;
+            }
+            if let Ok(Some(status)) = broker.broker.try_wait() {
+                panic!("MQTT broker exited with {}", status);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("MQTT broker not listening on {} after 5s", listen);
+    }
+}
+
+impl Drop for TestBroker {
+    fn drop(&mut self) {
+        let _ = self.broker.kill();
+        let _ = self.broker.wait();
+    }
+}
+
+/// Throwaway PostgreSQL cluster in a temp dir, reachable over a Unix socket
+/// and deleted on drop. Uses the server binaries from `PG_BIN`, `pg_config
+/// --bindir` or `PATH`; when running as root it runs them as `postgres`.
+pub struct TestDatabase {
+    pub url: String,
+    dir: PathBuf,
+    server: Child,
+}
+
+impl TestDatabase {
+    /// Panics when no PostgreSQL server is installed: the database tests are
+    /// part of `cargo test` and must not pass without running
+    pub async fn start() -> Self {
+        let bin = postgres_bin().expect(
+            "PostgreSQL server binaries (initdb, postgres) not found in PG_BIN, \
+             `pg_config --bindir` or PATH; install them or point PG_BIN at them",
+        );
+        let dir = std::env::temp_dir().join(format!("ingestor-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+
+        // The server refuses to run as root
+        let as_user = is_root().then_some("postgres");
+        if let Some(user) = as_user {
+            let status = Command::new("chown").args([user]).arg(&dir).status().unwrap();
+            assert!(status.success(), "cannot chown {} to {}", dir.display(), user);
+        }
+        let command = |program: &str| {
+            let program = bin.join(program);
+            match as_user {
+                Some(user) => {
+                    let mut command = Command::new("setpriv");
+                    command
+                        .arg(format!("--reuid={}", user))
+                        .arg(format!("--regid={}", user))
+                        .arg("--init-groups")
+                        .arg(program);
+                    command
+                }
+                None => Command::new(program),
+            }
+        };
+
+        let data = dir.join("data");
+        let output = command("initdb")
+            .arg("-D")
+            .arg(&data)
+            .args(["-U", "iot", "--auth=trust", "-E", "UTF8", "--no-sync"])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "initdb failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let log = std::fs::File::create(dir.join("server.log")).unwrap();
+        let server = command("postgres")
+            .arg("-D")
+            .arg(&data)
+            .arg("-k")
+            .arg(&dir)
+            .args(["-c", "listen_addresses=", "-c", "fsync=off", "-c", "synchronous_commit=off"])
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .unwrap();
+        let mut database = Self {
+            url: format!("postgres://iot@localhost/postgres?host={}", dir.display()),
+            dir,
+            server,
+        };
+        database.wait_ready().await;
+        database
+    }
+
+    /// Connection pool with the migrations applied
+    pub async fn pool(&self) -> PgPool {
+        crate::db::make_pool(&self.url).await.unwrap()
+    }
+
+    async fn wait_ready(&mut self) {
+        for _ in 0..200 {
+            if PgConnection::connect(&self.url).await.is_ok() {
+                return;
+            }
+            if let Ok(Some(status)) = self.server.try_wait() {
+                panic!("postgres exited with {}: {}", status, self.log());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("postgres not ready after 10s: {}", self.log());
+    }
+
+    fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("server.log")).unwrap_or_default()
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        // SIGQUIT is an immediate shutdown, which also ends the backends
+        let _ = Command::new("kill")
+            .args(["-QUIT", &self.server.id().to_string()])
+            .status();
+        let _ = self.server.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn postgres_bin() -> Option<PathBuf> {
+    let has_server = |dir: &Path| dir.join("initdb").is_file() && dir.join("postgres").is_file();
+    if let Some(dir) = std::env::var_os("PG_BIN").map(PathBuf::from) {
+        return has_server(&dir).then_some(dir);
+    }
+    let from_pg_config = Command::new("pg_config")
+        .arg("--bindir")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()));
+    from_pg_config
+        .into_iter()
+        .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()))
+        .find(|dir| has_server(dir))
+}
+
+fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .is_ok_and(|output| output.stdout.starts_with(b"0\n"))
+}
@@ -0,0 +1,743 @@
+use crate::errors::{Error, Result};
+use crate::metrics::{CHANNEL_FULL_TOTAL, LANE_DEPTH, LANE_SHED_TOTAL, SPOOL_REPLAYED_TOTAL};
+use crate::model::Envelope;
+use crate::spool::Spool;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Per-device sample counters are reset past this many devices
+const MAX_SAMPLED_DEVICES: usize = 100_000;
+
+/// How often a spilling lane checks whether its spool can be replayed
+const SPOOL_REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a lane does once its depth reaches the high-water mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backpressure {
+    /// Wait for room once the lane is full, stalling the MQTT event loop;
+    /// the high-water mark does not apply
+    Block,
+    /// Discard the incoming message
+    DropNewest,
+    /// Discard the oldest queued message to make room
+    DropOldest,
+    /// Keep one in `n` messages per device, discard the rest
+    Sample { n: u64 },
+    /// Append to the on-disk spool, replayed once the lane drains
+    Spill,
+}
+
+impl FromStr for Backpressure {
+    type Err = String;
+
+    /// Parses `block`, `drop_newest`, `drop_oldest`, `spill` or `sample:<n>`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "block" => Ok(Backpressure::Block),
+            "drop_newest" => Ok(Backpressure::DropNewest),
+            "drop_oldest" => Ok(Backpressure::DropOldest),
+            "spill" => Ok(Backpressure::Spill),
+            other => match other.strip_prefix("sample:").map(str::parse) {
+                Some(Ok(n)) if n > 0 => Ok(Backpressure::Sample { n }),
+                _ => Err(format!("unknown overload policy: {}", other)),
+            },
+        }
+    }
+}
+
+/// One priority lane; earlier lanes are drained first
+#[derive(Debug, Clone, Deserialize)]
+pub struct LaneConfig {
+    pub name: String,
+    pub capacity: usize,
+    #[serde(default = "default_backpressure")]
+    pub backpressure: Backpressure,
+    /// Depth at which `backpressure` kicks in; defaults to 90% of capacity
+    #[serde(default)]
+    pub high_water_mark: Option<usize>,
+    /// Topic prefixes routed to this lane
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+fn default_backpressure() -> Backpressure {
+    Backpressure::Block
+}
+
+impl LaneConfig {
+    fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+            .unwrap_or(self.capacity * 9 / 10)
+            .clamp(1, self.capacity.max(1))
+    }
+}
+
+/// Lane layout, loaded from the JSON file named by `LANES_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+pub struct LanesConfig {
+    /// Lanes in priority order, highest first
+    pub lanes: Vec<LaneConfig>,
+    /// Lane for messages matching no topic prefix or payload flag
+    pub default_lane: String,
+    /// Directory for lanes using the `spill` policy
+    #[serde(default = "default_spool_dir")]
+    pub spool_dir: String,
+}
+
+fn default_spool_dir() -> String {
+    "spool".to_string()
+}
+
+impl LanesConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&contents)?;
+        if config.lanes.is_empty() || config.lane_index(&config.default_lane).is_none() {
+            return Err(crate::errors::Error::Validation(format!(
+                "default_lane {:?} is not one of the configured lanes",
+                config.default_lane
+            )));
+        }
+        Ok(config)
+    }
+
+    /// A single lane, the layout used when no lanes are configured
+    pub fn single(
+        capacity: usize,
+        backpressure: Backpressure,
+        high_water_mark: Option<usize>,
+        spool_dir: String,
+    ) -> Self {
+        Self {
+            lanes: vec![LaneConfig {
+                name: "default".to_string(),
+                capacity,
+                backpressure,
+                high_water_mark,
+                topics: Vec::new(),
+            }],
+            default_lane: "default".to_string(),
+            spool_dir,
+        }
+    }
+
+    fn lane_index(&self, name: &str) -> Option<usize> {
+        self.lanes.iter().position(|l| l.name == name)
+    }
+}
+
+/// State shared by both halves of a lane
+#[derive(Debug)]
+struct LaneShared {
+    /// The lane's queue; the sending side pops its head to drop the oldest message
+    rx: Mutex<mpsc::Receiver<Envelope>>,
+    /// Messages seen over the high-water mark per device (sample)
+    sampled: Mutex<HashMap<String, u64>>,
+}
+
+impl LaneShared {
+    /// Returns true for the first and then every `n`th message from a device
+    fn keep_sample(&self, device_id: &str, n: u64) -> bool {
+        let mut sampled = self.sampled.lock().unwrap();
+        if sampled.len() >= MAX_SAMPLED_DEVICES && !sampled.contains_key(device_id) {
+            sampled.clear();
+        }
+        let count = sampled.entry(device_id.to_string()).or_insert(0);
+        *count += 1;
+        (*count - 1).is_multiple_of(n)
+    }
+}
+
+/// Sending half of one lane
+#[derive(Debug, Clone)]
+pub struct Lane {
+    pub name: String,
+    pub backpressure: Backpressure,
+    pub high_water_mark: usize,
+    pub tx: mpsc::Sender<Envelope>,
+    topics: Vec<String>,
+    shared: Arc<LaneShared>,
+    spool: Option<Arc<Spool>>,
+}
+
+impl Lane {
+    fn depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// Records the lane's current depth in its gauge
+    pub fn update_depth(&self) {
+        LANE_DEPTH
+            .with_label_values(&[&self.name])
+            .set(self.depth() as f64);
+    }
+
+    fn record_shed(&self, action: &str) {
+        LANE_SHED_TOTAL.with_label_values(&[&self.name, action]).inc();
+    }
+
+    /// Queues a message, applying the overload policy above the high-water mark.
+    ///
+    /// Returns `Ok(false)` if the message was shed, `Ok(true)` if it was queued
+    /// or spooled. Only `Block` waits for the receiver, and only while the lane
+    /// is full; the other policies shed the new message when it is still full.
+    pub async fn send(&self, envelope: Envelope) -> Result<bool> {
+        let blocking = matches!(self.backpressure, Backpressure::Block);
+        let envelope = if blocking || self.depth() < self.high_water_mark {
+            match self.tx.try_send(envelope) {
+                Ok(()) => return Ok(true),
+                Err(mpsc::error::TrySendError::Full(t)) => {
+                    CHANNEL_FULL_TOTAL.inc();
+                    t
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => return Err(Error::ChannelSend),
+            }
+        } else {
+            envelope
+        };
+
+        match self.backpressure {
+            Backpressure::Block => {
+                self.tx
+                    .send(envelope)
+                    .await
+                    .map_err(|_| Error::ChannelSend)?;
+                Ok(true)
+            }
+            Backpressure::DropNewest => {
+                self.record_shed("drop_newest");
+                Ok(false)
+            }
+            Backpressure::DropOldest => {
+                if self.shared.rx.lock().unwrap().try_recv().is_ok() {
+                    self.record_shed("drop_oldest");
+                }
+                self.try_enqueue(envelope)
+            }
+            Backpressure::Sample { n } => {
+                if !self.shared.keep_sample(&envelope.telemetry.device_id, n) {
+                    self.record_shed("sample");
+                    return Ok(false);
+                }
+                self.try_enqueue(envelope)
+            }
+            Backpressure::Spill => {
+                if let Some(spool) = &self.spool {
+                    match spool.append(&envelope) {
+                        Ok(()) => {
+                            self.record_shed("spill");
+                            return Ok(true);
+                        }
+                        Err(e) => error!("Failed to spool message on lane {}: {}", self.name, e),
+                    }
+                }
+                self.record_shed("drop_newest");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Queues without waiting, shedding the message if the lane is full
+    fn try_enqueue(&self, envelope: Envelope) -> Result<bool> {
+        match self.tx.try_send(envelope) {
+            Ok(()) => Ok(true),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.record_shed("drop_newest");
+                Ok(false)
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(Error::ChannelSend),
+        }
+    }
+}
+
+/// Routes validated readings into priority lanes
+#[derive(Debug, Clone)]
+pub struct LaneSenders {
+    lanes: Vec<Lane>,
+    default_lane: usize,
+}
+
+impl LaneSenders {
+    /// Picks the lane for a message: payload flag first, then topic prefix, then default.
+    ///
+    /// Lanes drain in priority order, so a flagged reading overtakes earlier
+    /// readings of the same device queued in lower lanes. Per-device order
+    /// only holds for devices that always land in the same lane.
+    pub fn route(&self, topic: &str, envelope: &Envelope) -> &Lane {
+        let flagged = envelope
+            .priority
+            .as_deref()
+            .and_then(|p| self.lanes.iter().find(|l| l.name == p));
+        let by_topic = || {
+            self.lanes
+                .iter()
+                .find(|l| l.topics.iter().any(|prefix| topic.starts_with(prefix)))
+        };
+
+        flagged
+            .or_else(by_topic)
+            .unwrap_or(&self.lanes[self.default_lane])
+    }
+}
+
+/// Receiving half of all lanes
+#[derive(Debug)]
+pub struct LaneReceivers {
+    names: Vec<String>,
+    shared: Vec<Arc<LaneShared>>,
+    depth: LaneDepth,
+}
+
+/// How full the lanes are, readable away from the receivers
+#[derive(Debug, Clone)]
+pub struct LaneDepth {
+    txs: Vec<mpsc::WeakSender<Envelope>>,
+}
+
+impl LaneDepth {
+    /// Messages queued across all lanes; zero once they are closed
+    pub fn depth(&self) -> usize {
+        self.txs
+            .iter()
+            .filter_map(|tx| tx.upgrade())
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum()
+    }
+
+    /// Total capacity of all lanes
+    pub fn capacity(&self) -> usize {
+        self.txs
+            .iter()
+            .filter_map(|tx| tx.upgrade())
+            .map(|tx| tx.max_capacity())
+            .sum()
+    }
+}
+
+impl LaneReceivers {
+    /// A view of the lane depths that does not keep the lanes open
+    pub fn depth(&self) -> LaneDepth {
+        self.depth.clone()
+    }
+
+    /// Receives the next message, always preferring higher-priority lanes.
+    ///
+    /// Returns `None` once every lane is closed and drained.
+    pub async fn recv(&mut self) -> Option<Envelope> {
+        std::future::poll_fn(|cx| {
+            let mut closed = 0;
+            for (name, shared) in self.names.iter().zip(&self.shared) {
+                let mut rx = shared.rx.lock().unwrap();
+                match rx.poll_recv(cx) {
+                    Poll::Ready(Some(t)) => {
+                        LANE_DEPTH.with_label_values(&[name]).set(rx.len() as f64);
+                        return Poll::Ready(Some(t));
+                    }
+                    Poll::Ready(None) => closed += 1,
+                    Poll::Pending => {}
+                }
+            }
+            if closed == self.shared.len() {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Creates the channels for every configured lane
+pub fn channels(config: &LanesConfig) -> Result<(LaneSenders, LaneReceivers)> {
+    let mut lanes = Vec::with_capacity(config.lanes.len());
+    let mut names = Vec::with_capacity(config.lanes.len());
+    let mut shared = Vec::with_capacity(config.lanes.len());
+    let mut weak_txs = Vec::with_capacity(config.lanes.len());
+
+    for lane in &config.lanes {
+        let (tx, rx) = mpsc::channel(lane.capacity.max(1));
+        weak_txs.push(tx.downgrade());
+        let lane_shared = Arc::new(LaneShared {
+            rx: Mutex::new(rx),
+            sampled: Mutex::new(HashMap::new()),
+        });
+        let spool = match lane.backpressure {
+            Backpressure::Spill => Some(Arc::new(Spool::open(&config.spool_dir, &lane.name)?)),
+            _ => None,
+        };
+        lanes.push(Lane {
+            name: lane.name.clone(),
+            backpressure: lane.backpressure,
+            high_water_mark: lane.high_water_mark(),
+            tx,
+            topics: lane.topics.clone(),
+            shared: lane_shared.clone(),
+            spool,
+        });
+        names.push(lane.name.clone());
+        shared.push(lane_shared);
+    }
+
+    let default_lane = config.lane_index(&config.default_lane).unwrap_or(0);
+    Ok((
+        LaneSenders { lanes, default_lane },
+        LaneReceivers {
+            names,
+            shared,
+            depth: LaneDepth { txs: weak_txs },
+        },
+    ))
+}
+
+/// Starts a replay task for every lane with a spool
+pub fn spawn_spool_replay(senders: &LaneSenders) {
+    for lane in senders.lanes.iter().filter(|l| l.spool.is_some()) {
+        let lane = lane.clone();
+        tokio::spawn(async move {
+            run_spool_replay(lane).await;
+        });
+    }
+}
+
+/// Feeds spooled messages back into the lane whenever it drops below half its high-water mark
+async fn run_spool_replay(lane: Lane) {
+    let Some(spool) = lane.spool.clone() else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(SPOOL_REPLAY_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        if lane.depth() >= lane.high_water_mark / 2 {
+            continue;
+        }
+
+        let files = match spool.take() {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Failed to read spool for lane {}: {}", lane.name, e);
+                continue;
+            }
+        };
+
+        for path in files {
+            let records = match Spool::read(&path) {
+                Ok(records) => records,
+                Err(e) => {
+                    warn!("Failed to read spool file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            info!(
+                "Replaying {} spooled messages into lane {}",
+                records.len(),
+                lane.name
+            );
+            for envelope in records {
+                if lane.tx.send(envelope).await.is_err() {
+                    return;
+                }
+                SPOOL_REPLAYED_TOTAL.with_label_values(&[&lane.name]).inc();
+            }
+            lane.update_depth();
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove spool file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Telemetry;
+    use chrono::Utc;
+
+    fn config() -> LanesConfig {
+        serde_json::from_str(
+            r#"{
+                "lanes": [
+                    { "name": "alarm", "capacity": 10, "topics": ["telemetry/alarm/"] },
+                    { "name": "routine", "capacity": 10, "backpressure": "drop_newest" }
+                ],
+                "default_lane": "routine"
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn sample(device_id: &str, priority: Option<&str>) -> Envelope {
+        Envelope {
+            priority: priority.map(str::to_string),
+            ..Telemetry::new(device_id, Utc::now(), 25.0, 60.0, 80.0).into()
+        }
+    }
+
+    #[test]
+    fn test_example_config_parses() {
+        let config: LanesConfig =
+            serde_json::from_str(include_str!("../../deploy/lanes.example.json")).unwrap();
+        assert!(config.lane_index(&config.default_lane).is_some());
+    }
+
+    #[test]
+    fn test_route() {
+        let (senders, _receivers) = channels(&config()).unwrap();
+
+        let t = sample("dev-1", None);
+        assert_eq!(senders.route("telemetry/alarm/dev-1", &t).name, "alarm");
+        assert_eq!(senders.route("telemetry/dev-1", &t).name, "routine");
+
+        let flagged = sample("dev-1", Some("alarm"));
+        assert_eq!(senders.route("telemetry/dev-1", &flagged).name, "alarm");
+        assert_eq!(senders.route("telemetry/dev-1", &flagged).backpressure, Backpressure::Block);
+
+        let unknown = sample("dev-1", Some("urgent"));
+        assert_eq!(senders.route("telemetry/dev-1", &unknown).name, "routine");
+    }
+
+    #[test]
+    fn test_high_priority_drained_first() {
+        tokio_test::block_on(async {
+            let (senders, mut receivers) = channels(&config()).unwrap();
+
+            for i in 0..3 {
+                let t = sample(&format!("routine-{}", i), None);
+                senders.route("telemetry/x", &t).tx.send(t.clone()).await.unwrap();
+            }
+            let alarm = sample("alarm-0", Some("alarm"));
+            senders.route("telemetry/x", &alarm).tx.send(alarm.clone()).await.unwrap();
+
+            assert_eq!(receivers.recv().await.unwrap().telemetry.device_id, "alarm-0");
+            assert_eq!(receivers.recv().await.unwrap().telemetry.device_id, "routine-0");
+
+            drop(senders);
+            assert_eq!(receivers.recv().await.unwrap().telemetry.device_id, "routine-1");
+            assert_eq!(receivers.recv().await.unwrap().telemetry.device_id, "routine-2");
+            assert!(receivers.recv().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_depth_across_lanes() {
+        tokio_test::block_on(async {
+            let (senders, mut receivers) = channels(&config()).unwrap();
+            let depth = receivers.depth();
+            assert_eq!((depth.depth(), depth.capacity()), (0, 20));
+
+            send_all(&senders, &["a", "b"]).await;
+            let alarm = sample("c", None);
+            senders.route("telemetry/alarm/c", &alarm).send(alarm.clone()).await.unwrap();
+            assert_eq!(depth.depth(), 3);
+
+            receivers.recv().await.unwrap();
+            assert_eq!(depth.depth(), 2);
+
+            // The view does not keep the lanes open
+            drop(senders);
+            assert_eq!((depth.depth(), depth.capacity()), (0, 0));
+            assert_eq!(drain_receivers(receivers).await, 2);
+        });
+    }
+
+    async fn drain_receivers(mut receivers: LaneReceivers) -> usize {
+        let mut received = 0;
+        while receivers.recv().await.is_some() {
+            received += 1;
+        }
+        received
+    }
+
+    #[test]
+    fn test_flagged_reading_overtakes_same_device() {
+        tokio_test::block_on(async {
+            let (senders, mut receivers) = channels(&config()).unwrap();
+
+            let routine = sample("dev-1", None);
+            senders.route("telemetry/dev-1", &routine).send(routine.clone()).await.unwrap();
+            let mut alarm = sample("dev-1", Some("alarm"));
+            alarm.telemetry.temperature = 90.0;
+            senders.route("telemetry/dev-1", &alarm).send(alarm.clone()).await.unwrap();
+
+            // Same device, but the later alarm reading is received first
+            drop(senders);
+            assert_eq!(receivers.recv().await.unwrap().telemetry.temperature, 90.0);
+            assert_eq!(receivers.recv().await.unwrap().telemetry.temperature, 25.0);
+        });
+    }
+
+    /// Sends without ever polling the receivers, so a send that waits for
+    /// room in the lane times out
+    async fn send_all(senders: &LaneSenders, device_ids: &[&str]) -> Vec<bool> {
+        let mut accepted = Vec::new();
+        for device_id in device_ids {
+            let t = sample(device_id, None);
+            let send = senders.route("telemetry/x", &t).send(t.clone());
+            let sent = tokio::time::timeout(Duration::from_secs(1), send)
+                .await
+                .unwrap_or_else(|_| panic!("send of {} waited for the receiver", device_id));
+            accepted.push(sent.unwrap());
+        }
+        accepted
+    }
+
+    async fn drain(senders: LaneSenders, mut receivers: LaneReceivers) -> Vec<String> {
+        drop(senders);
+        let mut received = Vec::new();
+        while let Some(t) = receivers.recv().await {
+            received.push(t.telemetry.device_id);
+        }
+        received
+    }
+
+    #[test]
+    fn test_backpressure_from_str() {
+        assert_eq!("block".parse::<Backpressure>(), Ok(Backpressure::Block));
+        assert_eq!("DROP_OLDEST".parse::<Backpressure>(), Ok(Backpressure::DropOldest));
+        assert_eq!("sample:5".parse::<Backpressure>(), Ok(Backpressure::Sample { n: 5 }));
+        assert!("sample:0".parse::<Backpressure>().is_err());
+        assert!("shed".parse::<Backpressure>().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_waits_only_when_full() {
+        let config = LanesConfig::single(3, Backpressure::Block, Some(1), "spool".into());
+        let (senders, receivers) = channels(&config).unwrap();
+
+        // Past the high-water mark, but with room in the lane
+        let start = tokio::time::Instant::now();
+        assert_eq!(send_all(&senders, &["a", "b", "c"]).await, [true, true, true]);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        let t = sample("d", None);
+        let full = senders.route("telemetry/x", &t).send(t.clone());
+        assert!(tokio::time::timeout(Duration::from_secs(1), full).await.is_err());
+        assert_eq!(drain(senders, receivers).await, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        tokio_test::block_on(async {
+            let config = LanesConfig::single(4, Backpressure::DropNewest, Some(2), "spool".into());
+            let (senders, receivers) = channels(&config).unwrap();
+
+            let accepted = send_all(&senders, &["a", "b", "c"]).await;
+            assert_eq!(accepted, [true, true, false]);
+            assert_eq!(drain(senders, receivers).await, ["a", "b"]);
+        });
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        tokio_test::block_on(async {
+            let config = LanesConfig::single(4, Backpressure::DropOldest, Some(2), "spool".into());
+            let (senders, receivers) = channels(&config).unwrap();
+
+            let accepted = send_all(&senders, &["a", "b", "c"]).await;
+            assert_eq!(accepted, [true, true, true]);
+            assert_eq!(drain(senders, receivers).await, ["b", "c"]);
+        });
+    }
+
+    #[test]
+    fn test_drop_oldest_at_capacity() {
+        tokio_test::block_on(async {
+            let config = LanesConfig::single(2, Backpressure::DropOldest, Some(2), "spool".into());
+            let (senders, receivers) = channels(&config).unwrap();
+
+            let accepted = send_all(&senders, &["a", "b", "c", "d"]).await;
+            assert_eq!(accepted, [true, true, true, true]);
+            assert_eq!(drain(senders, receivers).await, ["c", "d"]);
+        });
+    }
+
+    #[test]
+    fn test_sample_at_capacity() {
+        tokio_test::block_on(async {
+            let config =
+                LanesConfig::single(2, Backpressure::Sample { n: 2 }, Some(2), "spool".into());
+            let (senders, receivers) = channels(&config).unwrap();
+
+            // The third "a" is kept by sampling but the lane is full, so it is shed too
+            let accepted = send_all(&senders, &["a", "b", "a", "a"]).await;
+            assert_eq!(accepted, [true, true, false, false]);
+            assert_eq!(drain(senders, receivers).await, ["a", "b"]);
+        });
+    }
+
+    #[test]
+    fn test_sample_per_device() {
+        tokio_test::block_on(async {
+            let config =
+                LanesConfig::single(10, Backpressure::Sample { n: 2 }, Some(1), "spool".into());
+            let (senders, receivers) = channels(&config).unwrap();
+
+            // First message queues normally, the rest are over the high-water mark
+            let accepted = send_all(&senders, &["a", "a", "a", "a", "b"]).await;
+            assert_eq!(accepted, [true, true, false, true, true]);
+            assert_eq!(drain(senders, receivers).await, ["a", "a", "a", "b"]);
+        });
+    }
+
+    #[test]
+    fn test_spill() {
+        tokio_test::block_on(async {
+            let dir = std::env::temp_dir().join(format!("lanes-test-{}", uuid::Uuid::new_v4()));
+            let config = LanesConfig::single(
+                4,
+                Backpressure::Spill,
+                Some(1),
+                dir.to_string_lossy().into_owned(),
+            );
+            let (senders, receivers) = channels(&config).unwrap();
+
+            let accepted = send_all(&senders, &["a", "b", "c"]).await;
+            assert_eq!(accepted, [true, true, true]);
+
+            let spool = Spool::open(&dir, "default").unwrap();
+            let files = spool.take().unwrap();
+            let spilled: Vec<String> = Spool::read(&files[0])
+                .unwrap()
+                .into_iter()
+                .map(|t| t.telemetry.device_id)
+                .collect();
+            assert_eq!(spilled, ["b", "c"]);
+            assert_eq!(drain(senders, receivers).await, ["a"]);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_failed_spill_drops_newest() {
+        tokio_test::block_on(async {
+            let dir = std::env::temp_dir().join(format!("lanes-test-{}", uuid::Uuid::new_v4()));
+            let config = LanesConfig::single(
+                1,
+                Backpressure::Spill,
+                Some(1),
+                dir.to_string_lossy().into_owned(),
+            );
+            let (senders, receivers) = channels(&config).unwrap();
+            // A file where the spool directory was makes every append fail
+            std::fs::remove_dir(&dir).unwrap();
+            std::fs::write(&dir, b"").unwrap();
+
+            let accepted = send_all(&senders, &["a", "b"]).await;
+            assert_eq!(accepted, [true, false]);
+            assert_eq!(drain(senders, receivers).await, ["a"]);
+
+            std::fs::remove_file(&dir).unwrap();
+        });
+    }
+}
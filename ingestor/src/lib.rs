@@ -0,0 +1,38 @@
+//! IoT telemetry ingestion: MQTT in, validated batches into PostgreSQL, and
+//! a REST API to read them back.
+//!
+//! The stages are reusable on their own ([`validate::validate`],
+//! [`batching::run_batcher`], [`db::insert_batch`]) or wired together with
+//! [`pipeline::Pipeline`]; the `ingestor` binary is one such wiring.
+
+mod api_error;
+mod dedup;
+mod grafana;
+mod openapi;
+mod query;
+mod spool;
+
+pub mod adaptive;
+pub mod auth;
+pub mod batching;
+pub mod db;
+pub mod errors;
+pub mod jwt;
+pub mod lanes;
+pub mod metrics;
+pub mod model;
+pub mod mqtt;
+pub mod pipeline;
+pub mod ratelimit;
+pub mod rest;
+pub mod sink;
+pub mod stream;
+pub mod tenant;
+pub mod validate;
+
+/// Throwaway MQTT broker and PostgreSQL for tests, here and in `ingestor/tests/`
+#[cfg(any(test, feature = "test-harness"))]
+pub mod harness;
+
+pub use model::{Envelope, Telemetry};
+pub use pipeline::Pipeline;
@@ -1,5 +1,8 @@
 use lazy_static::lazy_static;
-use prometheus::{Counter, Encoder, Gauge, Histogram, HistogramOpts, Opts, Registry, TextEncoder};
+use prometheus::{
+    Counter, CounterVec, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, Opts,
+    Registry, TextEncoder,
+};
 
 lazy_static! {
     pub static ref REGISTRY: Registry = Registry::new();
@@ -33,16 +36,212 @@ lazy_static! {
         ])
     )
     .unwrap();
-    pub static ref BATCH_SIZE: Gauge = Gauge::with_opts(Opts::new(
-        "ingestor_batch_size",
-        "Current batch size being processed"
-    ))
-    .unwrap();
     pub static ref CHANNEL_FULL_TOTAL: Counter = Counter::with_opts(Opts::new(
         "ingestor_channel_full_total",
         "Total number of times channel was full (backpressure events)"
     ))
     .unwrap();
+    pub static ref DUPLICATES_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_duplicates_total",
+        "Total redelivered readings dropped by the dedup window"
+    ))
+    .unwrap();
+    pub static ref ROWS_SENT_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_rows_sent_total",
+        "Total rows sent to the DB in batch writes"
+    ))
+    .unwrap();
+    pub static ref ROWS_INSERTED_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_rows_inserted_total",
+        "Total rows actually inserted or updated by batch writes"
+    ))
+    .unwrap();
+    pub static ref ROWS_DISCARDED_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_rows_discarded_total",
+        "Total rows sent to the DB but not written due to the conflict policy"
+    ))
+    .unwrap();
+    pub static ref RATE_LIMITED_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_rate_limited_total",
+        "Total messages over their device or topic rate limit"
+    ))
+    .unwrap();
+    pub static ref RATE_LIMIT_SAMPLED_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_rate_limit_sampled_total",
+        "Total over-limit messages let through by sampling"
+    ))
+    .unwrap();
+    pub static ref RATE_LIMITED_DEVICE_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_rate_limited_device_total",
+            "Over-limit messages per device (devices past the tracking cap are labelled \"other\")"
+        ),
+        &["device_id"]
+    )
+    .unwrap();
+    pub static ref LANE_DEPTH: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "ingestor_lane_depth",
+            "Messages waiting in each priority lane"
+        ),
+        &["lane"]
+    )
+    .unwrap();
+    pub static ref LANE_SHED_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_lane_shed_total",
+            "Messages shed by each lane's overload policy (drop_newest, drop_oldest, sample, spill)"
+        ),
+        &["lane", "action"]
+    )
+    .unwrap();
+    pub static ref SPOOL_REPLAYED_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_spool_replayed_total",
+            "Spooled messages fed back into each lane"
+        ),
+        &["lane"]
+    )
+    .unwrap();
+    pub static ref WORKER_BATCH_SIZE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "ingestor_worker_batch_size",
+            "Current batch size being processed per batch worker"
+        ),
+        &["worker"]
+    )
+    .unwrap();
+    pub static ref WORKER_INGEST_LATENCY_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "ingestor_worker_ingest_latency_seconds",
+            "Time taken to ingest batch into DB per batch worker"
+        )
+        .buckets(vec![
+            0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0
+        ]),
+        &["worker"]
+    )
+    .unwrap();
+    pub static ref WORKER_ROWS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_worker_rows_total",
+            "Total records flushed to DB per batch worker"
+        ),
+        &["worker"]
+    )
+    .unwrap();
+    pub static ref WORKER_QUEUE_DEPTH: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "ingestor_worker_queue_depth",
+            "Records waiting in each batch worker's queue"
+        ),
+        &["worker"]
+    )
+    .unwrap();
+    pub static ref ADAPTIVE_BATCH_SIZE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "ingestor_adaptive_batch_size",
+            "Batch size currently chosen by adaptive batching per batch worker"
+        ),
+        &["worker"]
+    )
+    .unwrap();
+    pub static ref ADAPTIVE_FLUSH_INTERVAL_SECONDS: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "ingestor_adaptive_flush_interval_seconds",
+            "Flush interval currently chosen by adaptive batching per batch worker"
+        ),
+        &["worker"]
+    )
+    .unwrap();
+    pub static ref TENANT_MESSAGES_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_tenant_messages_total",
+            "Valid messages accepted per tenant"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    pub static ref TENANT_QUOTA_EXCEEDED_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_tenant_quota_exceeded_total",
+            "Messages dropped for exceeding their tenant's quota"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    pub static ref TENANT_RETENTION_DELETED_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_tenant_retention_deleted_total",
+            "Rows deleted by each tenant's retention setting"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+    pub static ref STREAM_SUBSCRIBERS: Gauge = Gauge::with_opts(Opts::new(
+        "ingestor_stream_subscribers",
+        "Clients connected to the live telemetry stream"
+    ))
+    .unwrap();
+    pub static ref STREAM_LAGGED_TOTAL: Counter = Counter::with_opts(Opts::new(
+        "ingestor_stream_lagged_total",
+        "Readings skipped by live stream subscribers that fell behind"
+    ))
+    .unwrap();
+    pub static ref SINK_ROWS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_sink_rows_total",
+            "Rows stored by each sink"
+        ),
+        &["sink"]
+    )
+    .unwrap();
+    pub static ref SINK_FAILURES_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_sink_failures_total",
+            "Failed batch writes by each sink, including retried ones"
+        ),
+        &["sink"]
+    )
+    .unwrap();
+    pub static ref SINK_WRITE_LATENCY_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "ingestor_sink_write_latency_seconds",
+            "Time taken to write a batch to each sink, retries included"
+        )
+        .buckets(vec![
+            0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0
+        ]),
+        &["sink"]
+    )
+    .unwrap();
+    pub static ref KAFKA_RECORDS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new(
+            "ingestor_kafka_records_total",
+            "Records forwarded to Kafka by outcome: acked, spooled or replayed"
+        ),
+        &["topic", "outcome"]
+    )
+    .unwrap();
+    pub static ref KAFKA_IN_FLIGHT: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "ingestor_kafka_in_flight",
+            "Records sent to Kafka and awaiting acknowledgement"
+        ),
+        &["topic"]
+    )
+    .unwrap();
+    pub static ref KAFKA_ACK_LATENCY_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "ingestor_kafka_ack_latency_seconds",
+            "Time from producing a batch to the acknowledgement of each record"
+        )
+        .buckets(vec![
+            0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0
+        ]),
+        &["topic"]
+    )
+    .unwrap();
 }
 
 pub fn init_metrics() {
@@ -59,10 +258,88 @@ pub fn init_metrics() {
     REGISTRY
         .register(Box::new(INGEST_LATENCY_SECONDS.clone()))
         .unwrap();
-    REGISTRY.register(Box::new(BATCH_SIZE.clone())).unwrap();
     REGISTRY
         .register(Box::new(CHANNEL_FULL_TOTAL.clone()))
         .unwrap();
+    REGISTRY
+        .register(Box::new(DUPLICATES_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ROWS_SENT_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ROWS_INSERTED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ROWS_DISCARDED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RATE_LIMITED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RATE_LIMIT_SAMPLED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RATE_LIMITED_DEVICE_TOTAL.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(LANE_DEPTH.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(LANE_SHED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(SPOOL_REPLAYED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(WORKER_BATCH_SIZE.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(WORKER_INGEST_LATENCY_SECONDS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(WORKER_ROWS_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(WORKER_QUEUE_DEPTH.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ADAPTIVE_BATCH_SIZE.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ADAPTIVE_FLUSH_INTERVAL_SECONDS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(TENANT_MESSAGES_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(TENANT_QUOTA_EXCEEDED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(TENANT_RETENTION_DELETED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(STREAM_SUBSCRIBERS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(STREAM_LAGGED_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(SINK_ROWS_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(SINK_FAILURES_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(SINK_WRITE_LATENCY_SECONDS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(KAFKA_RECORDS_TOTAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(KAFKA_IN_FLIGHT.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(KAFKA_ACK_LATENCY_SECONDS.clone()))
+        .unwrap();
 }
 
 pub fn gather_metrics() -> String {
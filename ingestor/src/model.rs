@@ -1,20 +1,82 @@
+use crate::query::{Aggregate, Fill};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use utoipa::ToSchema;
 
-/// IoT device telemetry data
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct Telemetry {
+pub use telemetry_model::Telemetry;
+
+/// A device's reading with the metadata the ingestor attaches to it on the
+/// way through; only `telemetry` is the device's payload
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub telemetry: Telemetry,
+    /// Priority lane requested by the payload's optional `priority` field
+    pub priority: Option<String>,
+    /// When the ingestor received the reading
+    pub received_at: Option<DateTime<Utc>>,
+    /// Owning tenant, resolved by the ingestor; never taken from the payload
+    pub tenant_id: Option<String>,
+}
+
+impl From<Telemetry> for Envelope {
+    fn from(telemetry: Telemetry) -> Self {
+        Self {
+            telemetry,
+            priority: None,
+            received_at: None,
+            tenant_id: None,
+        }
+    }
+}
+
+/// A stored reading as returned by the query API; measurements not selected
+/// with `fields` are left out
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct TelemetryRecord {
     pub device_id: String,
     pub timestamp: DateTime<Utc>,
-    pub temperature: f64,
-    pub humidity: f64,
-    pub battery: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub humidity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub battery: Option<f64>,
+}
+
+/// One bucket of a time-bucketed series; `value` is null for a gap
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SeriesPoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceSeries {
+    pub device_id: String,
+    pub points: Vec<SeriesPoint>,
+}
+
+/// Response of the series endpoint; each point is the start of its bucket
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SeriesResponse {
+    /// The charted measurement: `temperature`, `humidity` or `battery`
+    pub field: &'static str,
+    pub agg: Aggregate,
+    pub fill: Fill,
+    pub bucket_seconds: i64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub series: Vec<DeviceSeries>,
 }
 
 /// REST API response wrapper
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct TelemetryResponse {
-    pub data: Vec<Telemetry>,
+    pub data: Vec<TelemetryRecord>,
+    /// Number of readings in `data`
     pub total: usize,
     pub limit: usize,
     pub offset: usize,
@@ -0,0 +1,226 @@
+use crate::api_error::ApiError;
+use crate::model::Telemetry;
+use axum::{
+    extract::Path,
+    http::header,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+
+/// Document-level parts of the OpenAPI description of the REST API.
+///
+/// Paths and schemas are generated from the `#[utoipa::path]` handlers in
+/// `rest.rs` and the types they exchange; see `rest::openapi`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "IoT Telemetry Ingestor API",
+        description = "Query telemetry ingested over MQTT and administer the ingestor."
+    ),
+    components(schemas(Telemetry)),
+    modifiers(&Amend),
+    security(("bearerAuth" = []), ("apiKeyAuth" = [])),
+    tags(
+        (name = "telemetry", description = "Stored and live readings"),
+        (name = "admin", description = "Keys, tenants and rate limits"),
+        (name = "grafana", description = "Grafana JSON datasource"),
+        (name = "operations", description = "Monitoring")
+    )
+)]
+pub struct ApiDoc;
+
+/// What the derive cannot express: the security schemes, and no license
+/// (utoipa would otherwise copy the crate's, which is unset)
+struct Amend;
+
+impl Modify for Amend {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearerAuth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("A JWT signed by a key in `JWKS_PATH`, or an API key"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "apiKeyAuth",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+    }
+}
+
+/// Redoc's standalone bundle, embedded so the docs need no CDN; its version
+/// and source are recorded in `assets/README.md`
+const REDOC_JS: &str = include_str!("../assets/redoc.standalone.js");
+/// Served next to the bundle, where its banner points
+const REDOC_LICENSE: &str = include_str!("../assets/redoc.standalone.js.LICENSE.txt");
+
+/// Public routes serving `api` as JSON, with Swagger UI and Redoc; every
+/// asset is embedded in the binary
+pub fn docs<S>(api: utoipa::openapi::OpenApi) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let spec = Arc::new(api.to_json().expect("OpenAPI document serializes"));
+    let config = Arc::new(Config::from("/api/openapi.json"));
+    let index_config = config.clone();
+    Router::new()
+        .route(
+            "/api/openapi.json",
+            get(move || async move { ([(header::CONTENT_TYPE, "application/json")], spec.to_string()) }),
+        )
+        // Swagger UI loads its assets relative to the page
+        .route("/api/docs", get(|| async { Redirect::permanent("/api/docs/") }))
+        .route(
+            "/api/docs/",
+            get(move || swagger_ui(String::new(), index_config.clone())),
+        )
+        .route(
+            "/api/docs/*file",
+            get(move |Path(file): Path<String>| swagger_ui(file, config.clone())),
+        )
+        .route("/api/redoc", get(|| async { Html(REDOC_HTML) }))
+        .route(
+            "/api/redoc/redoc.standalone.js",
+            get(|| async { ([(header::CONTENT_TYPE, "text/javascript")], REDOC_JS) }),
+        )
+        .route(
+            "/api/redoc/redoc.standalone.js.LICENSE.txt",
+            get(|| async { REDOC_LICENSE }),
+        )
+}
+
+async fn swagger_ui(file: String, config: Arc<Config<'static>>) -> Result<Response, ApiError> {
+    match utoipa_swagger_ui::serve(&file, config) {
+        Ok(Some(file)) => Ok((
+            [(header::CONTENT_TYPE, file.content_type)],
+            file.bytes.into_owned(),
+        )
+            .into_response()),
+        Ok(None) => Err(ApiError::not_found(format!("No such file: {}", file))),
+        Err(e) => Err(ApiError::internal(&e)),
+    }
+}
+
+const REDOC_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>IoT Telemetry Ingestor API</title>
+</head>
+<body>
+  <redoc spec-url="/api/openapi.json"></redoc>
+  <script src="/api/redoc/redoc.standalone.js"></script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use sha2::{Digest, Sha256};
+    use std::collections::BTreeSet;
+
+    fn spec() -> Value {
+        serde_json::to_value(crate::rest::openapi()).unwrap()
+    }
+
+    fn refs<'a>(value: &'a Value, found: &mut BTreeSet<&'a str>) {
+        match value {
+            Value::Object(object) => {
+                if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                    found.insert(reference);
+                }
+                object.values().for_each(|v| refs(v, found));
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_every_route_is_documented() {
+        let spec = spec();
+        let operations: BTreeSet<(String, String)> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object().unwrap().keys().map(move |method| (method.clone(), path.clone()))
+            })
+            .collect();
+        let expected: BTreeSet<(String, String)> = [
+            ("get", "/metrics"),
+            ("get", "/api/v1/telemetry"),
+            ("get", "/api/v1/telemetry/series"),
+            ("get", "/api/v1/telemetry/stream"),
+            ("get", "/api/v1/telemetry/ws"),
+            ("get", "/api/v1/admin/rate-limits/offenders"),
+            ("get", "/api/v1/admin/api-keys"),
+            ("post", "/api/v1/admin/api-keys"),
+            ("delete", "/api/v1/admin/api-keys/{id}"),
+            ("get", "/api/v1/admin/tenants"),
+            ("get", "/grafana"),
+            ("post", "/grafana/search"),
+            ("post", "/grafana/query"),
+            ("post", "/grafana/annotations"),
+        ]
+        .into_iter()
+        .map(|(method, path)| (method.to_string(), path.to_string()))
+        .collect();
+        assert_eq!(operations, expected);
+
+        for (method, path) in &operations {
+            let operation = &spec["paths"][path][method];
+            let responses = operation["responses"].as_object().unwrap();
+            assert!(
+                responses.keys().any(|status| status.starts_with('2') || status == "101"),
+                "{} {} has no success response",
+                method,
+                path
+            );
+        }
+        // start/end are documented as RFC 3339 timestamps
+        let start = spec["paths"]["/api/v1/telemetry"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["name"] == "start")
+            .unwrap();
+        assert_eq!(start["schema"]["format"], "date-time");
+    }
+
+    #[test]
+    fn test_component_refs_resolve() {
+        let spec = spec();
+        let mut found = BTreeSet::new();
+        refs(&spec, &mut found);
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in &found {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.contains_key(name), "dangling {}", reference);
+        }
+        for name in ["Telemetry", "TelemetryResponse", "SeriesResponse", "ApiError", "ApiKey", "TenantConfig"] {
+            assert!(schemas.contains_key(name), "{} not documented", name);
+        }
+    }
+
+    /// The bundle is the one recorded in assets/README.md
+    #[test]
+    fn test_redoc_bundle_is_pinned() {
+        let digest = Sha256::digest(super::REDOC_JS.as_bytes());
+        assert_eq!(
+            hex::encode(digest),
+            "c7f107f5259486ec29f726db25e31a46a563b09f5209fd90c0371677e576d311"
+        );
+    }
+}
@@ -0,0 +1,579 @@
+use crate::errors::{Error, Result};
+use crate::lanes::LaneSenders;
+use crate::metrics::{INVALID_MESSAGES_TOTAL, TENANT_MESSAGES_TOTAL, VALID_MESSAGES_TOTAL};
+use crate::model::{Envelope, Telemetry};
+use crate::ratelimit::{Decision, RateLimiter};
+use crate::stream::Broadcaster;
+use crate::tenant::TenantRegistry;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+const MAX_RETRIES: u32 = 3;
+const INITIAL_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 2000;
+
+/// Where raw messages come from, e.g. an MQTT subscription
+pub trait Source: Send + 'static {
+    /// Hands every message to `handler` until the source ends or fails
+    fn run(self: Box<Self>, handler: Arc<Handler>) -> BoxFuture<'static, Result<()>>;
+}
+
+/// Turns a raw payload into a reading, with any metadata the payload carries
+pub trait Decoder: Send + Sync {
+    /// `Ok(None)` leaves the payload to the next decoder
+    fn decode(&self, topic: &str, payload: &[u8]) -> Result<Option<Envelope>>;
+}
+
+/// Rejects readings that must not be stored
+pub trait Validator: Send + Sync {
+    fn validate(&self, telemetry: &Telemetry) -> Result<()>;
+}
+
+/// Tags or filters readings after validation; returning `false` drops the reading
+pub trait Processor: Send + Sync {
+    fn process(&self, topic: &str, envelope: &mut Envelope) -> bool;
+}
+
+/// Destination of accepted readings, in the order they were added
+pub trait Sink: Send + Sync {
+    /// `Ok(false)` when the reading was shed; later sinks do not see it
+    fn send<'a>(&'a self, topic: &'a str, envelope: Envelope) -> BoxFuture<'a, Result<bool>>;
+
+    /// False to be skipped for now, so no copy of the reading is made
+    fn wants(&self) -> bool {
+        true
+    }
+}
+
+/// Decodes the JSON wire format published by devices, with its optional
+/// `"priority"` field
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonDecoder;
+
+#[derive(serde::Deserialize)]
+struct JsonPayload {
+    #[serde(flatten)]
+    telemetry: Telemetry,
+    #[serde(default)]
+    priority: Option<String>,
+}
+
+impl Decoder for JsonDecoder {
+    fn decode(&self, _topic: &str, payload: &[u8]) -> Result<Option<Envelope>> {
+        let payload: JsonPayload = serde_json::from_slice(payload)
+            .map_err(|e| Error::Validation(format!("JSON parse error: {}", e)))?;
+        Ok(Some(Envelope {
+            priority: payload.priority,
+            ..payload.telemetry.into()
+        }))
+    }
+}
+
+/// The measurement range checks of [`crate::validate::validate`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RangeValidator;
+
+impl Validator for RangeValidator {
+    fn validate(&self, telemetry: &Telemetry) -> Result<()> {
+        crate::validate::validate(telemetry)
+    }
+}
+
+impl<F> Validator for F
+where
+    F: Fn(&Telemetry) -> Result<()> + Send + Sync,
+{
+    fn validate(&self, telemetry: &Telemetry) -> Result<()> {
+        self(telemetry)
+    }
+}
+
+/// Per-device and per-topic rate limits
+impl Processor for RateLimiter {
+    fn process(&self, topic: &str, envelope: &mut Envelope) -> bool {
+        let device_id = &envelope.telemetry.device_id;
+        if self.check(topic, device_id) == Decision::Drop {
+            debug!("Device {} over rate limit, dropping message", device_id);
+            return false;
+        }
+        true
+    }
+}
+
+/// Tags readings with the owning tenant and charges its quota
+impl Processor for TenantRegistry {
+    fn process(&self, topic: &str, envelope: &mut Envelope) -> bool {
+        let tenant_id = self.resolve(topic, &envelope.telemetry.device_id);
+        if let Err(e) = self.check_quota(tenant_id) {
+            debug!("{}, dropping message", e);
+            return false;
+        }
+        envelope.tenant_id = Some(tenant_id.to_string());
+        true
+    }
+}
+
+impl<P: Processor + ?Sized> Processor for Arc<P> {
+    fn process(&self, topic: &str, envelope: &mut Envelope) -> bool {
+        (**self).process(topic, envelope)
+    }
+}
+
+/// Queues on the priority lane, shedding per its overload policy
+impl Sink for LaneSenders {
+    fn send<'a>(&'a self, topic: &'a str, envelope: Envelope) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let lane = self.route(topic, &envelope);
+            let result = lane.send(envelope).await;
+            lane.update_depth();
+            match result {
+                Ok(false) => debug!("Lane {} over high-water mark, message shed", lane.name),
+                Err(_) => error!("Channel closed, cannot send telemetry"),
+                Ok(true) => {}
+            }
+            result
+        })
+    }
+}
+
+/// Live stream for SSE/WebSocket clients, skipped while nobody is connected
+impl Sink for Broadcaster {
+    fn send<'a>(&'a self, _topic: &'a str, envelope: Envelope) -> BoxFuture<'a, Result<bool>> {
+        self.publish(envelope);
+        Box::pin(async { Ok(true) })
+    }
+
+    fn wants(&self) -> bool {
+        self.has_subscribers()
+    }
+}
+
+/// Plain channel, for embedding services that consume readings themselves
+impl Sink for mpsc::Sender<Envelope> {
+    fn send<'a>(&'a self, _topic: &'a str, envelope: Envelope) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            mpsc::Sender::send(self, envelope)
+                .await
+                .map(|()| true)
+                .map_err(|_| Error::ChannelSend)
+        })
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Arc<S> {
+    fn send<'a>(&'a self, topic: &'a str, envelope: Envelope) -> BoxFuture<'a, Result<bool>> {
+        (**self).send(topic, envelope)
+    }
+
+    fn wants(&self) -> bool {
+        (**self).wants()
+    }
+}
+
+/// Messages pushed by the embedding service as `(topic, payload)` pairs
+pub struct ChannelSource(pub mpsc::Receiver<(String, Vec<u8>)>);
+
+impl Source for ChannelSource {
+    fn run(self: Box<Self>, handler: Arc<Handler>) -> BoxFuture<'static, Result<()>> {
+        let mut rx = self.0;
+        Box::pin(async move {
+            while let Some((topic, payload)) = rx.recv().await {
+                let _ = handler.handle(&topic, &payload).await;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The stages between a source and the sinks
+pub struct Handler {
+    decoders: Vec<Box<dyn Decoder>>,
+    validators: Vec<Box<dyn Validator>>,
+    processors: Vec<Box<dyn Processor>>,
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl Handler {
+    /// Runs one message through the pipeline, retrying transient failures
+    /// with exponential backoff. Failures are logged and counted as invalid.
+    pub async fn handle(&self, topic: &str, payload: &[u8]) -> Result<()> {
+        let mut attempt = 0;
+        let mut backoff_ms = INITIAL_BACKOFF_MS;
+
+        loop {
+            attempt += 1;
+
+            match self.process(topic, payload).await {
+                Ok(()) => {
+                    if attempt > 1 {
+                        info!("Message processed successfully on attempt {}", attempt);
+                    }
+                    return Ok(());
+                }
+                Err(e) if attempt >= MAX_RETRIES || !is_retryable_error(&e) => {
+                    error!("Failed to process message: {}", e);
+                    INVALID_MESSAGES_TOTAL.inc();
+                    return Err(e);
+                }
+                Err(e) => {
+                    warn!(
+                        "Message processing failed (attempt {}/{}): {}. Retrying in {}ms...",
+                        attempt, MAX_RETRIES, e, backoff_ms
+                    );
+                    tokio::time::sleep(std::time::Duration::from_millis(backoff_ms)).await;
+                    backoff_ms = (backoff_ms * 2).min(MAX_BACKOFF_MS);
+                }
+            }
+        }
+    }
+
+    /// Process a single message. Readings dropped by a processor or shed by
+    /// a sink are not errors.
+    async fn process(&self, topic: &str, payload: &[u8]) -> Result<()> {
+        let mut envelope = self.decode(topic, payload)?;
+        envelope.received_at = Some(chrono::Utc::now());
+
+        for validator in &self.validators {
+            validator.validate(&envelope.telemetry)?;
+        }
+        for processor in &self.processors {
+            if !processor.process(topic, &mut envelope) {
+                return Ok(());
+            }
+        }
+
+        let tenant_id = envelope.tenant_id.clone();
+        let sinks: Vec<&dyn Sink> = self.sinks.iter().map(|s| s.as_ref()).filter(|s| s.wants()).collect();
+        let mut envelope = Some(envelope);
+        for (i, sink) in sinks.iter().enumerate() {
+            let reading = if i + 1 == sinks.len() {
+                envelope.take()
+            } else {
+                envelope.clone()
+            };
+            if !sink.send(topic, reading.expect("reading taken by the last sink")).await? {
+                return Ok(());
+            }
+        }
+
+        VALID_MESSAGES_TOTAL.inc();
+        if let Some(tenant_id) = tenant_id {
+            TENANT_MESSAGES_TOTAL.with_label_values(&[&tenant_id]).inc();
+        }
+        Ok(())
+    }
+
+    fn decode(&self, topic: &str, payload: &[u8]) -> Result<Envelope> {
+        for decoder in &self.decoders {
+            if let Some(envelope) = decoder.decode(topic, payload)? {
+                return Ok(envelope);
+            }
+        }
+        Err(Error::Validation(format!("No decoder for message on {}", topic)))
+    }
+}
+
+/// Determine if an error is retryable
+fn is_retryable_error(error: &Error) -> bool {
+    match error {
+        // Retryable errors
+        Error::ChannelSend => true, // Channel might be temporarily full
+        Error::Database(_) => true, // Database might be temporarily unavailable
+
+        // Non-retryable errors
+        Error::Validation(_) => false, // Bad data won't become valid with retry
+        Error::Config(_) => false,
+        Error::RateLimited(_) => false, // Retrying only burns more quota
+        Error::Mqtt(_) => false,       // MQTT errors handled at connection level
+        Error::Json(_) => false,       // JSON parse errors won't be fixed by retry
+        Error::Kafka(_) => false,
+        Error::Io(_) => false,
+        Error::Migration(_) => false,
+    }
+}
+
+/// An ingest pipeline: source → decoders → validators → processors → sinks.
+///
+/// ```ignore
+/// let pipeline = Pipeline::builder()
+///     .source(MqttSource::new("localhost", 1883, "ingestor-1"))
+///     .validator(RangeValidator)
+///     .processor(tenants)
+///     .sink(lanes)
+///     .build()?;
+/// pipeline.run().await?;
+/// ```
+pub struct Pipeline {
+    source: Box<dyn Source>,
+    handler: Arc<Handler>,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// The stages, for feeding messages in alongside the source
+    pub fn handler(&self) -> Arc<Handler> {
+        self.handler.clone()
+    }
+
+    /// Runs until the source ends
+    pub async fn run(self) -> Result<()> {
+        self.source.run(self.handler).await
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineBuilder {
+    source: Option<Box<dyn Source>>,
+    decoders: Vec<Box<dyn Decoder>>,
+    validators: Vec<Box<dyn Validator>>,
+    processors: Vec<Box<dyn Processor>>,
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl PipelineBuilder {
+    pub fn source(mut self, source: impl Source) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Decoders are tried in order; [`JsonDecoder`] is used if none are added
+    pub fn decoder(mut self, decoder: impl Decoder + 'static) -> Self {
+        self.decoders.push(Box::new(decoder));
+        self
+    }
+
+    pub fn validator(mut self, validator: impl Validator + 'static) -> Self {
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    pub fn processor(mut self, processor: impl Processor + 'static) -> Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// The stages without a source, for services that call [`Handler::handle`] themselves
+    pub fn build_handler(mut self) -> Handler {
+        if self.decoders.is_empty() {
+            self.decoders.push(Box::new(JsonDecoder));
+        }
+        Handler {
+            decoders: self.decoders,
+            validators: self.validators,
+            processors: self.processors,
+            sinks: self.sinks,
+        }
+    }
+
+    pub fn build(mut self) -> Result<Pipeline> {
+        let source = self
+            .source
+            .take()
+            .ok_or_else(|| Error::Config("Pipeline has no source".to_string()))?;
+        Ok(Pipeline {
+            source,
+            handler: Arc::new(self.build_handler()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lanes;
+    use chrono::Utc;
+
+    fn payload(device_id: &str, temperature: f64) -> Vec<u8> {
+        serde_json::to_vec(&Telemetry::new(device_id, Utc::now(), temperature, 60.0, 80.0)).unwrap()
+    }
+
+    fn single_lane() -> (LaneSenders, lanes::LaneReceivers) {
+        lanes::channels(&lanes::LanesConfig::single(
+            10,
+            lanes::Backpressure::Block,
+            None,
+            "spool".to_string(),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        assert!(is_retryable_error(&Error::ChannelSend));
+        assert!(!is_retryable_error(&Error::Validation(
+            "test".to_string()
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_handle_valid() {
+        let (tx, mut rx) = single_lane();
+        let stream = Arc::new(Broadcaster::new(16));
+        let handler = Pipeline::builder()
+            .validator(RangeValidator)
+            .processor(TenantRegistry::default())
+            .sink(tx)
+            .sink(stream.clone())
+            .build_handler();
+        let mut live = stream.subscribe(Default::default());
+
+        handler.handle("telemetry/test-dev", &payload("test-dev", 25.0)).await.unwrap();
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.telemetry.device_id, "test-dev");
+        assert_eq!(received.tenant_id.as_deref(), Some("default"));
+        assert!(received.received_at.is_some());
+        assert!(matches!(
+            live.next().await,
+            Some(crate::stream::StreamItem::Reading(r)) if r.telemetry.device_id == "test-dev"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handle_invalid() {
+        let (tx, _rx) = single_lane();
+        let handler = Pipeline::builder().validator(RangeValidator).sink(tx).build_handler();
+
+        assert!(handler.handle("telemetry/test-dev", b"invalid json").await.is_err());
+        // Out of range
+        assert!(handler.handle("telemetry/test-dev", &payload("test-dev", 999.0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_custom_stages() {
+        struct Topic;
+        impl Decoder for Topic {
+            fn decode(&self, topic: &str, payload: &[u8]) -> Result<Option<Envelope>> {
+                let Some(device_id) = topic.strip_prefix("raw/") else { return Ok(None) };
+                let battery = std::str::from_utf8(payload).ok().and_then(|s| s.parse().ok());
+                Ok(battery.map(|battery| Telemetry::new(device_id, Utc::now(), 20.0, 50.0, battery).into()))
+            }
+        }
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let handler = Pipeline::builder()
+            .decoder(Topic)
+            .decoder(JsonDecoder)
+            .validator(|t: &Telemetry| match t.device_id.starts_with("dev-") {
+                true => Ok(()),
+                false => Err(Error::Validation("unknown device".to_string())),
+            })
+            .sink(tx)
+            .build_handler();
+
+        handler.handle("raw/dev-1", b"42").await.unwrap();
+        handler.handle("telemetry/dev-2", &payload("dev-2", 25.0)).await.unwrap();
+        assert!(handler.handle("raw/dev-3", b"low").await.is_err());
+        assert!(handler.handle("raw/other", b"42").await.is_err());
+
+        assert_eq!(rx.recv().await.unwrap().telemetry.battery, 42.0);
+        assert_eq!(rx.recv().await.unwrap().telemetry.device_id, "dev-2");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_rate_limited() {
+        let (tx, mut rx) = single_lane();
+        let limiter = RateLimiter::new(
+            serde_json::from_str(r#"{ "default": { "rate": 0, "burst": 1 } }"#).unwrap(),
+        );
+        let handler = Pipeline::builder().processor(limiter).sink(tx).build_handler();
+
+        for _ in 0..3 {
+            handler.handle("telemetry/test-dev", &payload("test-dev", 25.0)).await.unwrap();
+        }
+
+        drop(handler);
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_drop_newest_lane() {
+        let config: lanes::LanesConfig = serde_json::from_str(
+            r#"{
+                "lanes": [{ "name": "routine", "capacity": 1, "backpressure": "drop_newest" }],
+                "default_lane": "routine"
+            }"#,
+        )
+        .unwrap();
+        let (tx, mut rx) = lanes::channels(&config).unwrap();
+        let (live_tx, mut live_rx) = mpsc::channel(10);
+        let handler = Pipeline::builder().sink(tx).sink(live_tx).build_handler();
+
+        for device_id in ["dev-1", "dev-2"] {
+            handler.handle("telemetry/x", &payload(device_id, 25.0)).await.unwrap();
+        }
+
+        // The shed reading never reaches later sinks
+        drop(handler);
+        assert_eq!(rx.recv().await.unwrap().telemetry.device_id, "dev-1");
+        assert!(rx.recv().await.is_none());
+        assert_eq!(live_rx.recv().await.unwrap().telemetry.device_id, "dev-1");
+        assert!(live_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_tenant() {
+        let (tx, mut rx) = single_lane();
+        let tenants = TenantRegistry::new(
+            serde_json::from_str(
+                r#"{ "tenants": [{
+                    "id": "acme",
+                    "topic_prefixes": ["telemetry/acme/"],
+                    "quota": { "rate": 0, "burst": 1 }
+                }] }"#,
+            )
+            .unwrap(),
+        );
+        let handler = Pipeline::builder().processor(tenants).sink(tx).build_handler();
+
+        // Second message is over the tenant quota
+        for topic in ["telemetry/acme/telemetry/test-dev", "telemetry/acme/telemetry/test-dev", "telemetry/test-dev"] {
+            handler.handle(topic, &payload("test-dev", 25.0)).await.unwrap();
+        }
+
+        drop(handler);
+        assert_eq!(rx.recv().await.unwrap().tenant_id.as_deref(), Some("acme"));
+        assert_eq!(rx.recv().await.unwrap().tenant_id.as_deref(), Some("default"));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_runs_source() {
+        assert!(matches!(Pipeline::builder().build(), Err(Error::Config(_))));
+
+        let (messages, rx) = mpsc::channel(10);
+        let (tx, mut readings) = mpsc::channel(10);
+        let pipeline = Pipeline::builder()
+            .source(ChannelSource(rx))
+            .validator(RangeValidator)
+            .sink(tx)
+            .build()
+            .unwrap();
+        let running = tokio::spawn(pipeline.run());
+
+        for (device_id, temperature) in [("dev-1", 20.0), ("dev-2", 500.0), ("dev-3", 30.0)] {
+            messages
+                .send((format!("telemetry/{}", device_id), payload(device_id, temperature)))
+                .await
+                .unwrap();
+        }
+        drop(messages);
+        running.await.unwrap().unwrap();
+
+        assert_eq!(readings.recv().await.unwrap().telemetry.device_id, "dev-1");
+        assert_eq!(readings.recv().await.unwrap().telemetry.device_id, "dev-3");
+        assert!(readings.recv().await.is_none());
+    }
+}
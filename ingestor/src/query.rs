@@ -0,0 +1,688 @@
+use crate::api_error::ApiError;
+use crate::model::{DeviceSeries, SeriesPoint};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder};
+use utoipa::{IntoParams, ToSchema};
+
+/// Measurement columns that can be selected and filtered on
+pub const MEASUREMENTS: [&str; 3] = ["temperature", "humidity", "battery"];
+
+/// Points returned by the series endpoint when `points` is omitted
+const DEFAULT_POINTS: usize = 500;
+const MAX_POINTS: usize = 10_000;
+
+/// Window charted when the request has no `start`
+const DEFAULT_SPAN_HOURS: i64 = 24;
+
+/// Bucket widths, in seconds, chosen from so points line up on round times
+const NICE_WIDTHS: [i64; 18] = [
+    1, 2, 5, 10, 15, 30, 60, 120, 300, 600, 900, 1800, 3600, 7200, 10800, 21600, 43200, 86400,
+];
+
+/// Query string of the telemetry query API
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TelemetryQuery {
+    /// Tenant id; only honoured for callers not bound to a tenant
+    tenant: Option<String>,
+    /// Device ids, repeated (`device_id=a&device_id=b`) and/or comma-separated;
+    /// `*` and `?` make an entry a glob (`pump-*`)
+    #[serde(default)]
+    device_id: Vec<String>,
+    /// Only devices whose id starts with this prefix
+    device_prefix: Option<String>,
+    /// Inclusive lower bound on `timestamp`, RFC 3339 (e.g. `2025-10-05T00:00:00Z`).
+    /// For series, defaults to 24 hours before `end`
+    start: Option<DateTime<Utc>>,
+    /// Inclusive upper bound on `timestamp`, RFC 3339; must not be before `start`.
+    /// For series, defaults to now
+    end: Option<DateTime<Utc>>,
+    /// Measurements to return, repeated and/or comma-separated; all when empty
+    #[serde(default)]
+    #[param(inline, value_type = Vec<Measurement>)]
+    fields: Vec<String>,
+    /// Only readings with temperature below this value
+    temperature_lt: Option<f64>,
+    /// Only readings with temperature above this value
+    temperature_gt: Option<f64>,
+    /// Only readings with temperature in `low,high`, inclusive
+    #[param(example = "10,30")]
+    temperature_between: Option<String>,
+    /// Only readings with humidity below this value
+    humidity_lt: Option<f64>,
+    /// Only readings with humidity above this value
+    humidity_gt: Option<f64>,
+    /// Only readings with humidity in `low,high`, inclusive
+    #[param(example = "40,60")]
+    humidity_between: Option<String>,
+    /// Only readings with battery below this value
+    battery_lt: Option<f64>,
+    /// Only readings with battery above this value
+    battery_gt: Option<f64>,
+    /// Only readings with battery in `low,high`, inclusive
+    #[param(example = "0,20")]
+    battery_between: Option<String>,
+    /// Max readings returned; values above 1000 are capped
+    #[param(maximum = 1000, default = 100)]
+    pub limit: Option<usize>,
+    /// Readings to skip
+    #[param(default = 0)]
+    pub offset: Option<usize>,
+}
+
+/// Schema of the names in [`MEASUREMENTS`], for the API documentation
+#[derive(ToSchema)]
+#[schema(rename_all = "lowercase")]
+#[allow(dead_code)]
+pub enum Measurement {
+    Temperature,
+    Humidity,
+    Battery,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Predicate {
+    Lt(f64),
+    Gt(f64),
+    /// Inclusive on both ends
+    Between(f64, f64),
+}
+
+/// Validated filters of a telemetry query, rendered to SQL with bound parameters
+#[derive(Debug, Default)]
+pub struct TelemetryFilter {
+    tenant: Option<String>,
+    device_ids: Vec<String>,
+    /// `LIKE` patterns from globs and `device_prefix`
+    device_patterns: Vec<String>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    fields: Vec<&'static str>,
+    predicates: Vec<(&'static str, Predicate)>,
+}
+
+impl TelemetryFilter {
+    /// Validates `params`; a tenant-bound caller's `tenant` overrides the one requested
+    pub fn new(params: &TelemetryQuery, tenant: Option<&String>) -> Result<Self, ApiError> {
+        if let (Some(start), Some(end)) = (params.start, params.end) {
+            if start > end {
+                return Err(
+                    ApiError::bad_request("invalid_time_range", "start must not be after end")
+                        .with_details(json!({ "start": start, "end": end })),
+                );
+            }
+        }
+
+        let mut filter = Self {
+            tenant: tenant.or(params.tenant.as_ref()).cloned(),
+            start: params.start,
+            end: params.end,
+            ..Default::default()
+        };
+
+        for device_id in split_list(&params.device_id) {
+            if device_id.contains(['*', '?']) {
+                filter.device_patterns.push(glob_to_like(device_id));
+            } else {
+                filter.device_ids.push(device_id.to_string());
+            }
+        }
+        if let Some(prefix) = &params.device_prefix {
+            filter
+                .device_patterns
+                .push(format!("{}%", escape_like(prefix)));
+        }
+
+        for field in split_list(&params.fields) {
+            let column = MEASUREMENTS.iter().find(|m| **m == field).ok_or_else(|| {
+                ApiError::bad_request("invalid_field", format!("Unknown field: {}", field))
+                    .with_details(json!({ "field": field, "allowed": MEASUREMENTS }))
+            })?;
+            if !filter.fields.contains(column) {
+                filter.fields.push(column);
+            }
+        }
+
+        let predicates = [
+            ("temperature", params.temperature_lt, params.temperature_gt, &params.temperature_between),
+            ("humidity", params.humidity_lt, params.humidity_gt, &params.humidity_between),
+            ("battery", params.battery_lt, params.battery_gt, &params.battery_between),
+        ];
+        for (column, lt, gt, between) in predicates {
+            if let Some(value) = lt {
+                filter.predicates.push((column, Predicate::Lt(value)));
+            }
+            if let Some(value) = gt {
+                filter.predicates.push((column, Predicate::Gt(value)));
+            }
+            if let Some(range) = between {
+                let (low, high) = parse_between(range).ok_or_else(|| {
+                    ApiError::bad_request(
+                        "invalid_predicate",
+                        format!("{}_between must be two numbers, low,high", column),
+                    )
+                    .with_details(json!({ "parameter": format!("{}_between", column), "value": range }))
+                })?;
+                filter.predicates.push((column, Predicate::Between(low, high)));
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Selected measurement columns; all of them when none were requested
+    pub fn columns(&self) -> &[&'static str] {
+        if self.fields.is_empty() {
+            &MEASUREMENTS
+        } else {
+            &self.fields
+        }
+    }
+
+    /// Newest-first page of matching readings
+    pub fn select(&self, limit: usize, offset: usize) -> QueryBuilder<'_, Postgres> {
+        let mut query = QueryBuilder::new("SELECT device_id, ts AS timestamp");
+        for column in self.columns() {
+            query.push(", ").push(column);
+        }
+        query.push(" FROM telemetry");
+        self.push_where(&mut query);
+        query
+            .push(" ORDER BY ts DESC LIMIT ")
+            .push_bind(limit as i64)
+            .push(" OFFSET ")
+            .push_bind(offset as i64);
+        query
+    }
+
+    /// Appends the `WHERE` clause, if any, binding every value
+    pub fn push_where<'a>(&'a self, query: &mut QueryBuilder<'a, Postgres>) {
+        self.push_conditions(query, true);
+    }
+
+    fn push_conditions<'a>(&'a self, query: &mut QueryBuilder<'a, Postgres>, with_predicates: bool) {
+        let mut conditions = 0;
+        let mut next = |query: &mut QueryBuilder<'a, Postgres>| {
+            query.push(if conditions == 0 { " WHERE " } else { " AND " });
+            conditions += 1;
+        };
+
+        if let Some(tenant) = &self.tenant {
+            next(query);
+            query.push("tenant_id = ").push_bind(tenant);
+        }
+
+        if !self.device_ids.is_empty() || !self.device_patterns.is_empty() {
+            next(query);
+            query.push("(");
+            let mut alternatives = query.separated(" OR ");
+            if !self.device_ids.is_empty() {
+                alternatives
+                    .push("device_id = ANY(")
+                    .push_bind_unseparated(&self.device_ids)
+                    .push_unseparated(")");
+            }
+            for pattern in &self.device_patterns {
+                alternatives
+                    .push("device_id LIKE ")
+                    .push_bind_unseparated(pattern);
+            }
+            query.push(")");
+        }
+
+        if let Some(start) = self.start {
+            next(query);
+            query.push("ts >= ").push_bind(start);
+        }
+        if let Some(end) = self.end {
+            next(query);
+            query.push("ts <= ").push_bind(end);
+        }
+
+        if with_predicates {
+            for (column, predicate) in &self.predicates {
+                next(query);
+                push_predicate(query, column, *predicate);
+            }
+        }
+    }
+
+    /// Periods in which readings of a device continuously matched the value
+    /// predicates, earliest first
+    pub fn episodes(&self, limit: usize) -> Result<QueryBuilder<'_, Postgres>, ApiError> {
+        if self.predicates.is_empty() {
+            return Err(ApiError::bad_request(
+                "invalid_predicate",
+                "At least one value predicate such as battery_lt=20 is required",
+            ));
+        }
+        let mut query = QueryBuilder::new(
+            "SELECT device_id, min(ts) AS started, max(ts) AS ended, count(*) AS readings \
+             FROM (SELECT device_id, ts, matched, \
+             row_number() OVER (PARTITION BY device_id ORDER BY ts) \
+             - row_number() OVER (PARTITION BY device_id, matched ORDER BY ts) AS island \
+             FROM (SELECT device_id, ts, (",
+        );
+        let mut predicates = self.predicates.iter();
+        while let Some((column, predicate)) = predicates.next() {
+            push_predicate(&mut query, column, *predicate);
+            if predicates.len() > 0 {
+                query.push(" AND ");
+            }
+        }
+        query.push(") AS matched FROM telemetry");
+        self.push_conditions(&mut query, false);
+        query
+            .push(") t) s WHERE matched GROUP BY device_id, island ORDER BY started LIMIT ")
+            .push_bind(limit as i64);
+        Ok(query)
+    }
+}
+
+fn push_predicate<'a>(query: &mut QueryBuilder<'a, Postgres>, column: &str, predicate: Predicate) {
+    query.push(column);
+    match predicate {
+        Predicate::Lt(value) => {
+            query.push(" < ").push_bind(value);
+        }
+        Predicate::Gt(value) => {
+            query.push(" > ").push_bind(value);
+        }
+        Predicate::Between(low, high) => {
+            query
+                .push(" BETWEEN ")
+                .push_bind(low)
+                .push(" AND ")
+                .push_bind(high);
+        }
+    }
+}
+
+/// A run of consecutive readings of one device matching the value predicates
+#[derive(Debug, sqlx::FromRow)]
+pub struct Episode {
+    pub device_id: String,
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    pub readings: i64,
+}
+
+/// Entries of repeated and/or comma-separated parameters, trimmed and non-empty
+pub fn split_list(values: &[String]) -> impl Iterator<Item = &str> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `*` matches any run of characters and `?` exactly one
+fn glob_to_like(glob: &str) -> String {
+    escape_like(glob).replace('*', "%").replace('?', "_")
+}
+
+fn parse_between(range: &str) -> Option<(f64, f64)> {
+    let (low, high) = range.split_once(',')?;
+    let low: f64 = low.trim().parse().ok()?;
+    let high: f64 = high.trim().parse().ok()?;
+    (low <= high).then_some((low, high))
+}
+
+/// How readings in one bucket are combined
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    #[default]
+    Avg,
+    Min,
+    Max,
+    Last,
+    Count,
+}
+
+/// What an empty bucket holds; empty `count` buckets are always 0
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Fill {
+    /// Empty buckets are left out
+    #[default]
+    None,
+    Null,
+    /// The last value before the gap
+    Previous,
+    /// Interpolated between the values either side of the gap
+    Linear,
+}
+
+/// Series-specific parameters; device and value filters come from `TelemetryQuery`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SeriesQuery {
+    /// Measurement to chart
+    #[param(inline, value_type = Measurement)]
+    pub field: String,
+    /// How readings in a bucket are combined
+    #[serde(default)]
+    pub agg: Aggregate,
+    /// Empty buckets: left out (`none`), `null`, the `previous` value, or
+    /// `linear`ly interpolated
+    #[serde(default)]
+    pub fill: Fill,
+    /// Approximate number of points per series
+    #[param(minimum = 1, maximum = 10000, default = 500)]
+    pub points: Option<usize>,
+}
+
+/// A validated series request over `[start, end]`
+#[derive(Debug)]
+pub struct Series {
+    pub field: &'static str,
+    pub agg: Aggregate,
+    pub fill: Fill,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub bucket_seconds: i64,
+}
+
+impl Series {
+    /// Validates `params` and picks a bucket width; a missing time range
+    /// defaults to the last day and is written back into `filter`
+    pub fn new(params: &SeriesQuery, filter: &mut TelemetryFilter) -> Result<Self, ApiError> {
+        let field = MEASUREMENTS
+            .iter()
+            .find(|m| **m == params.field)
+            .ok_or_else(|| {
+                ApiError::bad_request("invalid_field", format!("Unknown field: {}", params.field))
+                    .with_details(json!({ "field": params.field, "allowed": MEASUREMENTS }))
+            })?;
+        let end = filter.end.unwrap_or_else(Utc::now);
+        let start = filter
+            .start
+            .unwrap_or(end - chrono::Duration::hours(DEFAULT_SPAN_HOURS));
+        let points = params.points.unwrap_or(DEFAULT_POINTS).clamp(1, MAX_POINTS);
+        filter.start = Some(start);
+        filter.end = Some(end);
+
+        Ok(Self {
+            field,
+            agg: params.agg,
+            fill: params.fill,
+            start,
+            end,
+            bucket_seconds: bucket_width((end - start).num_seconds(), points),
+        })
+    }
+
+    fn aggregate_sql(&self) -> String {
+        match self.agg {
+            Aggregate::Avg => format!("avg({})", self.field),
+            Aggregate::Min => format!("min({})", self.field),
+            Aggregate::Max => format!("max({})", self.field),
+            Aggregate::Last => format!("(array_agg({} ORDER BY ts DESC))[1]", self.field),
+            Aggregate::Count => format!("count({})::double precision", self.field),
+        }
+    }
+
+    /// One row per device and bucket, ordered by device then time; `filter`
+    /// must be the one passed to `new`
+    pub fn select<'a>(&'a self, filter: &'a TelemetryFilter) -> QueryBuilder<'a, Postgres> {
+        let mut query = QueryBuilder::new("WITH agg AS (SELECT device_id, date_bin(make_interval(secs => ");
+        query
+            .push_bind(self.bucket_seconds as f64)
+            .push("), ts, ")
+            .push_bind(self.start)
+            .push(") AS bucket, ")
+            .push(self.aggregate_sql())
+            .push(" AS value FROM telemetry");
+        filter.push_where(&mut query);
+        query.push(" GROUP BY device_id, bucket)");
+
+        if self.fill == Fill::None {
+            query.push(" SELECT device_id, bucket, value FROM agg");
+        } else {
+            // Every bucket of every device that has any reading in the range;
+            // an empty bucket counts zero readings
+            let missing = if self.agg == Aggregate::Count { "coalesce(a.value, 0)" } else { "a.value" };
+            query
+                .push(", grid AS (SELECT d.device_id, b.bucket, ")
+                .push(missing)
+                .push(" AS value FROM (SELECT DISTINCT device_id FROM agg) d CROSS JOIN generate_series(")
+                .push_bind(self.start)
+                .push("::timestamptz, ")
+                .push_bind(self.end)
+                .push("::timestamptz, make_interval(secs => ")
+                .push_bind(self.bucket_seconds as f64)
+                .push(")) AS b(bucket) LEFT JOIN agg a ON a.device_id = d.device_id AND a.bucket = b.bucket)");
+            query.push(match self.fill {
+                Fill::None | Fill::Null => " SELECT device_id, bucket, value FROM grid",
+                // Rows after each non-null value share its running count
+                Fill::Previous => {
+                    " SELECT device_id, bucket, first_value(value) OVER (PARTITION BY device_id, prev_grp ORDER BY bucket) AS value \
+                     FROM (SELECT *, count(value) OVER (PARTITION BY device_id ORDER BY bucket) AS prev_grp FROM grid) g"
+                }
+                Fill::Linear => {
+                    " SELECT device_id, bucket, CASE WHEN value IS NOT NULL THEN value \
+                     WHEN prev_bucket IS NOT NULL AND next_bucket IS NOT NULL THEN prev_value + (next_value - prev_value) \
+                     * extract(epoch FROM bucket - prev_bucket) / extract(epoch FROM next_bucket - prev_bucket) END AS value \
+                     FROM (SELECT device_id, bucket, value, \
+                     first_value(value) OVER (PARTITION BY device_id, prev_grp ORDER BY bucket) AS prev_value, \
+                     first_value(CASE WHEN value IS NOT NULL THEN bucket END) OVER (PARTITION BY device_id, prev_grp ORDER BY bucket) AS prev_bucket, \
+                     first_value(value) OVER (PARTITION BY device_id, next_grp ORDER BY bucket DESC) AS next_value, \
+                     first_value(CASE WHEN value IS NOT NULL THEN bucket END) OVER (PARTITION BY device_id, next_grp ORDER BY bucket DESC) AS next_bucket \
+                     FROM (SELECT *, count(value) OVER (PARTITION BY device_id ORDER BY bucket) AS prev_grp, \
+                     count(value) OVER (PARTITION BY device_id ORDER BY bucket DESC) AS next_grp FROM grid) g) w"
+                }
+            });
+        }
+        query.push(" ORDER BY device_id, bucket");
+        query
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SeriesRow {
+    device_id: String,
+    bucket: DateTime<Utc>,
+    value: Option<f64>,
+}
+
+/// Groups rows ordered by device into one series per device
+pub fn group_rows(rows: Vec<SeriesRow>) -> Vec<DeviceSeries> {
+    let mut series: Vec<DeviceSeries> = Vec::new();
+    for row in rows {
+        let point = SeriesPoint {
+            timestamp: row.bucket,
+            value: row.value,
+        };
+        match series.last_mut() {
+            Some(last) if last.device_id == row.device_id => last.points.push(point),
+            _ => series.push(DeviceSeries {
+                device_id: row.device_id,
+                points: vec![point],
+            }),
+        }
+    }
+    series
+}
+
+/// Smallest round width giving at most `points` buckets over `span_seconds`
+pub fn bucket_width(span_seconds: i64, points: usize) -> i64 {
+    let needed = (span_seconds.max(1) as f64 / points.max(1) as f64).ceil() as i64;
+    match NICE_WIDTHS.iter().find(|width| **width >= needed) {
+        Some(width) => *width,
+        // Whole days beyond that
+        None => (needed + 86399) / 86400 * 86400,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query_string: &str) -> TelemetryQuery {
+        serde_html_form::from_str(query_string).unwrap()
+    }
+
+    #[test]
+    fn test_sql_binds_every_value() {
+        let params = query(
+            "device_id=a,b&device_id=pump-*&fields=battery&battery_lt=20\
+             &temperature_between=10,30&start=2025-10-05T00:00:00Z",
+        );
+        let filter = TelemetryFilter::new(&params, Some(&"acme".to_string())).unwrap();
+
+        assert_eq!(filter.device_ids, ["a", "b"]);
+        assert_eq!(filter.device_patterns, ["pump-%"]);
+        assert_eq!(
+            filter.select(100, 0).sql(),
+            "SELECT device_id, ts AS timestamp, battery FROM telemetry \
+             WHERE tenant_id = $1 AND (device_id = ANY($2) OR device_id LIKE $3) \
+             AND ts >= $4 AND temperature BETWEEN $5 AND $6 AND battery < $7 \
+             ORDER BY ts DESC LIMIT $8 OFFSET $9"
+        );
+    }
+
+    #[test]
+    fn test_no_filters() {
+        let filter = TelemetryFilter::new(&TelemetryQuery::default(), None).unwrap();
+        assert_eq!(
+            filter.select(10, 20).sql(),
+            "SELECT device_id, ts AS timestamp, temperature, humidity, battery FROM telemetry \
+             ORDER BY ts DESC LIMIT $1 OFFSET $2"
+        );
+    }
+
+    #[test]
+    fn test_globs_and_prefix_are_escaped() {
+        assert_eq!(glob_to_like("dev_?*"), "dev\\__%");
+        let filter = TelemetryFilter::new(&query("device_prefix=50%25"), None).unwrap();
+        assert_eq!(filter.device_patterns, ["50\\%%"]);
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        for query_string in [
+            "fields=pressure",
+            "battery_between=20",
+            "battery_between=30,20",
+            "start=2025-10-05T12:00:00Z&end=2025-10-05T00:00:00Z",
+        ] {
+            assert!(
+                TelemetryFilter::new(&query(query_string), None).is_err(),
+                "{}",
+                query_string
+            );
+        }
+    }
+
+    #[test]
+    fn test_episodes_sql() {
+        let filter = TelemetryFilter::new(&query("device_id=a&battery_lt=20&temperature_gt=40"), None).unwrap();
+        assert_eq!(
+            filter.episodes(10).unwrap().sql(),
+            "SELECT device_id, min(ts) AS started, max(ts) AS ended, count(*) AS readings \
+             FROM (SELECT device_id, ts, matched, \
+             row_number() OVER (PARTITION BY device_id ORDER BY ts) \
+             - row_number() OVER (PARTITION BY device_id, matched ORDER BY ts) AS island \
+             FROM (SELECT device_id, ts, (temperature > $1 AND battery < $2) AS matched \
+             FROM telemetry WHERE (device_id = ANY($3))) t) s \
+             WHERE matched GROUP BY device_id, island ORDER BY started LIMIT $4"
+        );
+
+        // Without a predicate every reading would be an alert
+        assert!(TelemetryFilter::new(&query("device_id=a"), None).unwrap().episodes(10).is_err());
+    }
+
+    #[test]
+    fn test_bucket_width() {
+        // A day at 500 points needs 173 s buckets; the next round width is 5 minutes
+        assert_eq!(bucket_width(86_400, 500), 300);
+        assert_eq!(bucket_width(60, 500), 1);
+        assert_eq!(bucket_width(0, 500), 1);
+        // 30 days at 10 points: 3 days
+        assert_eq!(bucket_width(30 * 86_400, 10), 3 * 86_400);
+    }
+
+    fn build_series(query_string: &str) -> (TelemetryFilter, Result<Series, ApiError>) {
+        let mut filter = TelemetryFilter::new(&query(query_string), None).unwrap();
+        let series = Series::new(&serde_html_form::from_str(query_string).unwrap(), &mut filter);
+        (filter, series)
+    }
+
+    #[test]
+    fn test_series_sql() {
+        let (filter, series) = build_series(
+            "device_id=dev-1&field=battery&agg=last&start=2025-10-05T00:00:00Z&end=2025-10-05T01:00:00Z&points=60",
+        );
+        let series = series.unwrap();
+        assert_eq!(series.bucket_seconds, 60);
+        assert_eq!(
+            series.select(&filter).sql(),
+            "WITH agg AS (SELECT device_id, date_bin(make_interval(secs => $1), ts, $2) AS bucket, \
+             (array_agg(battery ORDER BY ts DESC))[1] AS value FROM telemetry \
+             WHERE (device_id = ANY($3)) AND ts >= $4 AND ts <= $5 GROUP BY device_id, bucket) \
+             SELECT device_id, bucket, value FROM agg ORDER BY device_id, bucket"
+        );
+
+        // Without a range the last day is charted
+        let (filter, series) = build_series("field=temperature&fill=previous");
+        let series = series.unwrap();
+        assert_eq!(filter.end, Some(series.end));
+        assert_eq!((series.end - series.start).num_hours(), 24);
+        assert!(series.select(&filter).sql().contains("generate_series("));
+
+        assert!(build_series("field=pressure").1.is_err());
+    }
+
+    #[tokio::test]
+    async fn series_gap_filling() {
+        let database = crate::harness::TestDatabase::start().await;
+        let pool = database.pool().await;
+        let device_id = format!("series-{}", uuid::Uuid::new_v4());
+        for (offset_secs, temperature) in [(10, 10.0), (40, 12.0), (90, 20.0), (300, 60.0)] {
+            sqlx::query(
+                "INSERT INTO telemetry (device_id, ts, temperature, humidity, battery) \
+                 VALUES ($1, '2024-01-01T00:00:00Z'::timestamptz + make_interval(secs => $2), $3, 50, 90)",
+            )
+            .bind(&device_id)
+            .bind(offset_secs as f64)
+            .bind(temperature)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let range = "start=2024-01-01T00:00:00Z&end=2024-01-01T00:06:00Z&points=6&field=temperature";
+        for (options, expected) in [
+            ("fill=none", vec![Some(11.0), Some(20.0), Some(60.0)]),
+            ("fill=null&agg=max", vec![Some(12.0), Some(20.0), None, None, None, Some(60.0), None]),
+            ("fill=previous", vec![Some(11.0), Some(20.0), Some(20.0), Some(20.0), Some(20.0), Some(60.0), Some(60.0)]),
+            ("fill=linear", vec![Some(11.0), Some(20.0), Some(30.0), Some(40.0), Some(50.0), Some(60.0), None]),
+            ("fill=null&agg=count", vec![Some(2.0), Some(1.0), Some(0.0), Some(0.0), Some(0.0), Some(1.0), Some(0.0)]),
+        ] {
+            let (filter, series) = build_series(&format!("device_id={}&{}&{}", device_id, range, options));
+            let series = series.unwrap();
+            let rows: Vec<SeriesRow> = series
+                .select(&filter)
+                .build_query_as()
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            let values: Vec<Option<f64>> = group_rows(rows)[0].points.iter().map(|p| p.value).collect();
+            assert_eq!(values, expected, "{}", options);
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::errors::Result;
+use crate::metrics::{RATE_LIMITED_DEVICE_TOTAL, RATE_LIMITED_TOTAL, RATE_LIMIT_SAMPLED_TOTAL};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+/// Label used for devices beyond the per-device metric cardinality cap
+const OTHER_DEVICES_LABEL: &str = "other";
+
+/// Token bucket parameters: sustained messages per second and burst size
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+pub struct Limit {
+    /// Sustained messages per second
+    pub rate: f64,
+    pub burst: f64,
+}
+
+/// Limit applied to devices whose `device_id` starts with `prefix`
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrefixLimit {
+    pub prefix: String,
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl PrefixLimit {
+    fn limit(&self) -> Limit {
+        Limit {
+            rate: self.rate,
+            burst: self.burst,
+        }
+    }
+}
+
+/// What to do with a message over its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverLimitAction {
+    /// Drop every over-limit message
+    Drop,
+    /// Let one in `n` over-limit messages through per device
+    Sample { n: u64 },
+}
+
+/// Rate limit settings, loaded from the JSON file named by `RATE_LIMIT_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Per-device limit for devices matching no device type
+    pub default: Limit,
+    /// Per-device limits by device type, matched on `device_id` prefix (longest wins)
+    #[serde(default)]
+    pub device_types: Vec<PrefixLimit>,
+    /// Shared limits for every device publishing under a topic prefix
+    #[serde(default)]
+    pub topics: Vec<PrefixLimit>,
+    #[serde(default = "default_action")]
+    pub action: OverLimitAction,
+    /// Max devices tracked with their own bucket and metric label
+    #[serde(default = "default_max_tracked_devices")]
+    pub max_tracked_devices: usize,
+}
+
+fn default_action() -> OverLimitAction {
+    OverLimitAction::Drop
+}
+
+fn default_max_tracked_devices() -> usize {
+    10_000
+}
+
+impl RateLimitConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: Limit,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            last: now,
+        }
+    }
+
+    pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+        let ok = self.has_token(now);
+        if ok {
+            self.take();
+        }
+        ok
+    }
+
+    /// Refills the bucket up to `now` and tells whether a message fits
+    fn has_token(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        self.last = now;
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+/// Outcome of a rate limit check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Over limit but let through by sampling
+    Sampled,
+    Drop,
+}
+
+/// Per-device over-limit counts, as listed by the admin endpoint
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Offender {
+    pub device_id: String,
+    pub limited: u64,
+    pub sampled: u64,
+}
+
+#[derive(Debug, Default)]
+struct OffenderCounts {
+    limited: u64,
+    sampled: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Device buckets; the least recently seen is evicted once full
+    devices: LruCache<String, TokenBucket>,
+    topics: Vec<TokenBucket>,
+    offenders: HashMap<String, OffenderCounts>,
+}
+
+/// Token bucket rate limiter keyed by device and topic prefix
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        let topics = config
+            .topics
+            .iter()
+            .map(|t| TokenBucket::new(t.limit(), now))
+            .collect();
+        let max_devices = NonZeroUsize::new(config.max_tracked_devices).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            config,
+            state: Mutex::new(State {
+                devices: LruCache::new(max_devices),
+                topics,
+                offenders: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Charges one message to its device and topic buckets if all have room
+    pub fn check(&self, topic: &str, device_id: &str) -> Decision {
+        self.check_at(topic, device_id, Instant::now())
+    }
+
+    /// Tokens are only taken when the device and every matching topic bucket
+    /// have one, so a device over its own limit does not drain the topic
+    /// buckets it shares with well-behaved devices.
+    fn check_at(&self, topic: &str, device_id: &str, now: Instant) -> Decision {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            devices, topics, ..
+        } = &mut *state;
+
+        let device = devices.get_or_insert_mut(device_id.to_string(), || {
+            TokenBucket::new(self.device_limit(device_id), now)
+        });
+        if device.has_token(now) {
+            let mut matching: Vec<&mut TokenBucket> = self
+                .config
+                .topics
+                .iter()
+                .zip(topics.iter_mut())
+                .filter(|(rule, _)| topic.starts_with(&rule.prefix))
+                .map(|(_, bucket)| bucket)
+                .collect();
+            if matching.iter_mut().all(|bucket| bucket.has_token(now)) {
+                device.take();
+                matching.into_iter().for_each(TokenBucket::take);
+                return Decision::Allow;
+            }
+        }
+
+        let tracked = state.offenders.contains_key(device_id)
+            || state.offenders.len() < self.config.max_tracked_devices;
+        let label = if tracked { device_id } else { OTHER_DEVICES_LABEL };
+        let counts = state.offenders.entry(label.to_string()).or_default();
+        counts.limited += 1;
+        RATE_LIMITED_TOTAL.inc();
+        RATE_LIMITED_DEVICE_TOTAL.with_label_values(&[label]).inc();
+
+        match self.config.action {
+            OverLimitAction::Sample { n } if n > 0 && counts.limited.is_multiple_of(n) => {
+                counts.sampled += 1;
+                RATE_LIMIT_SAMPLED_TOTAL.inc();
+                Decision::Sampled
+            }
+            _ => Decision::Drop,
+        }
+    }
+
+    fn device_limit(&self, device_id: &str) -> Limit {
+        self.config
+            .device_types
+            .iter()
+            .filter(|t| device_id.starts_with(&t.prefix))
+            .max_by_key(|t| t.prefix.len())
+            .map(PrefixLimit::limit)
+            .unwrap_or(self.config.default)
+    }
+
+    /// Devices with the most over-limit messages, worst first
+    pub fn top_offenders(&self, limit: usize) -> Vec<Offender> {
+        let state = self.state.lock().unwrap();
+        let mut offenders: Vec<Offender> = state
+            .offenders
+            .iter()
+            .map(|(device_id, counts)| Offender {
+                device_id: device_id.clone(),
+                limited: counts.limited,
+                sampled: counts.sampled,
+            })
+            .collect();
+        offenders.sort_by(|a, b| {
+            b.limited
+                .cmp(&a.limited)
+                .then_with(|| a.device_id.cmp(&b.device_id))
+        });
+        offenders.truncate(limit);
+        offenders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> RateLimitConfig {
+        serde_json::from_str(
+            r#"{
+                "default": { "rate": 1, "burst": 2 },
+                "device_types": [{ "prefix": "cam-", "rate": 10, "burst": 5 }],
+                "topics": [{ "prefix": "telemetry/site-a/", "rate": 1, "burst": 3 }],
+                "max_tracked_devices": 2
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_example_config_parses() {
+        let config: RateLimitConfig =
+            serde_json::from_str(include_str!("../../deploy/rate_limits.example.json")).unwrap();
+        assert_eq!(config.action, OverLimitAction::Sample { n: 10 });
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("telemetry/dev-1", "dev-1", now), Decision::Allow);
+        assert_eq!(limiter.check_at("telemetry/dev-1", "dev-1", now), Decision::Allow);
+        assert_eq!(limiter.check_at("telemetry/dev-1", "dev-1", now), Decision::Drop);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check_at("telemetry/dev-1", "dev-1", later), Decision::Allow);
+    }
+
+    #[test]
+    fn test_device_type_limit() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            assert_eq!(limiter.check_at("telemetry/cam-1", "cam-1", now), Decision::Allow);
+        }
+        assert_eq!(limiter.check_at("telemetry/cam-1", "cam-1", now), Decision::Drop);
+    }
+
+    #[test]
+    fn test_topic_limit_is_shared() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        for device_id in ["cam-1", "cam-2", "cam-3"] {
+            let topic = format!("telemetry/site-a/{}", device_id);
+            assert_eq!(limiter.check_at(&topic, device_id, now), Decision::Allow);
+        }
+        assert_eq!(
+            limiter.check_at("telemetry/site-a/cam-4", "cam-4", now),
+            Decision::Drop
+        );
+    }
+
+    #[test]
+    fn test_flooding_device_does_not_starve_topic() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        // dev-1 takes two of the topic's three tokens, then is over its own limit
+        for _ in 0..10 {
+            limiter.check_at("telemetry/site-a/dev-1", "dev-1", now);
+        }
+        assert_eq!(limiter.check_at("telemetry/site-a/dev-2", "dev-2", now), Decision::Allow);
+    }
+
+    #[test]
+    fn test_refused_message_takes_no_topic_tokens() {
+        let mut config = config();
+        config.topics = serde_json::from_str(
+            r#"[
+                { "prefix": "telemetry/", "rate": 0, "burst": 2 },
+                { "prefix": "telemetry/site-a/", "rate": 0, "burst": 1 }
+            ]"#,
+        )
+        .unwrap();
+        let limiter = RateLimiter::new(config);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("telemetry/site-a/dev-1", "dev-1", now), Decision::Allow);
+        assert_eq!(limiter.check_at("telemetry/site-a/dev-2", "dev-2", now), Decision::Drop);
+        // The refusal by site-a left telemetry/ its second token
+        assert_eq!(limiter.check_at("telemetry/site-b/dev-3", "dev-3", now), Decision::Allow);
+        assert_eq!(limiter.check_at("telemetry/site-b/dev-4", "dev-4", now), Decision::Drop);
+    }
+
+    #[test]
+    fn test_sampling() {
+        let mut config = config();
+        config.action = OverLimitAction::Sample { n: 2 };
+        let limiter = RateLimiter::new(config);
+        let now = Instant::now();
+
+        limiter.check_at("t", "dev-1", now);
+        limiter.check_at("t", "dev-1", now);
+        assert_eq!(limiter.check_at("t", "dev-1", now), Decision::Drop);
+        assert_eq!(limiter.check_at("t", "dev-1", now), Decision::Sampled);
+    }
+
+    #[test]
+    fn test_least_recently_seen_device_evicted() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        for device_id in ["dev-1", "dev-1", "dev-2", "dev-2", "dev-1"] {
+            limiter.check_at("t", device_id, now);
+        }
+        // dev-2 is now the least recently seen, so dev-3 takes its place
+        limiter.check_at("t", "dev-3", now);
+        assert_eq!(limiter.check_at("t", "dev-1", now), Decision::Drop);
+        assert_eq!(limiter.check_at("t", "dev-2", now), Decision::Allow);
+    }
+
+    #[test]
+    fn test_top_offenders_capped() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        for (device_id, n) in [("dev-1", 3), ("dev-2", 5), ("dev-3", 4)] {
+            for _ in 0..n {
+                limiter.check_at("t", device_id, now);
+            }
+        }
+
+        // dev-3 showed up after the cap was reached
+        let top = limiter.top_offenders(10);
+        assert_eq!(top.len(), 3);
+        assert_eq!((top[0].device_id.as_str(), top[0].limited), ("dev-2", 3));
+        assert_eq!((top[1].device_id.as_str(), top[1].limited), ("other", 2));
+        assert_eq!((top[2].device_id.as_str(), top[2].limited), ("dev-1", 1));
+
+        assert_eq!(limiter.top_offenders(1).len(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::db::tenant_of;
+use crate::errors::{Error, Result};
+use crate::model::Envelope;
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+
+/// Writer schema of the Avro values, registered under `<topic>-value`
+pub const SCHEMA: &str = r#"{"type":"record","name":"Telemetry","namespace":"iot","fields":[{"name":"tenant_id","type":"string"},{"name":"device_id","type":"string"},{"name":"timestamp","type":{"type":"long","logicalType":"timestamp-micros"}},{"name":"temperature","type":"double"},{"name":"humidity","type":"double"},{"name":"battery","type":"double"},{"name":"received_at","type":["null",{"type":"long","logicalType":"timestamp-micros"}],"default":null}]}"#;
+
+/// First byte of the Confluent wire format, followed by the schema id
+const MAGIC: u8 = 0;
+
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Appends `value` as a zigzag varint, the encoding of Avro `long`s and of
+/// Kafka record fields
+pub(super) fn write_long(buf: &mut Vec<u8>, value: i64) {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_long(buf, value.len() as i64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Appends one reading in the Confluent framing: magic byte, big-endian
+/// schema id, then the Avro binary encoding of [`SCHEMA`]
+pub(super) fn encode(envelope: &Envelope, schema_id: i32, buf: &mut Vec<u8>) {
+    let t = &envelope.telemetry;
+    buf.push(MAGIC);
+    buf.extend_from_slice(&schema_id.to_be_bytes());
+    write_string(buf, tenant_of(envelope));
+    write_string(buf, &t.device_id);
+    write_long(buf, t.timestamp.timestamp_micros());
+    for value in [t.temperature, t.humidity, t.battery] {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+    match envelope.received_at {
+        None => write_long(buf, 0),
+        Some(received_at) => {
+            write_long(buf, 1);
+            write_long(buf, received_at.timestamp_micros());
+        }
+    }
+}
+
+/// Registers [`SCHEMA`] under `subject` with a Confluent-compatible schema
+/// registry (such as Redpanda's) and returns its id. Registering an
+/// identical schema again returns the existing id.
+pub(super) async fn register(url: &str, subject: &str) -> Result<i32> {
+    let error = |message: String| Error::Kafka(format!("schema registry {}: {}", url, message));
+    let client = reqwest::Client::builder()
+        .timeout(REGISTRY_TIMEOUT)
+        .build()
+        .map_err(|e| error(e.to_string()))?;
+    let response = client
+        .post(format!("{}/subjects/{}/versions", url.trim_end_matches('/'), subject))
+        .header(CONTENT_TYPE, "application/vnd.schemaregistry.v1+json")
+        .body(serde_json::json!({ "schema": SCHEMA }).to_string())
+        .send()
+        .await
+        .map_err(|e| error(e.to_string()))?;
+
+    let status = response.status();
+    let body = response.text().await.map_err(|e| error(e.to_string()))?;
+    if !status.is_success() {
+        return Err(error(format!("returned {}: {}", status, body)));
+    }
+    let value: serde_json::Value = serde_json::from_str(&body)?;
+    value["id"]
+        .as_i64()
+        .map(|id| id as i32)
+        .ok_or_else(|| error(format!("no schema id in {}", body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use crate::model::Telemetry;
+
+    #[test]
+    fn test_zigzag_varint() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (-1, vec![0x01]),
+            (1, vec![0x02]),
+            (-64, vec![0x7f]),
+            (64, vec![0x80, 0x01]),
+            (300, vec![0xd8, 0x04]),
+        ] {
+            let mut buf = Vec::new();
+            write_long(&mut buf, value);
+            assert_eq!(buf, expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_encode_confluent_framing() {
+        let timestamp = Utc.timestamp_micros(1_700_000_000_000_000).unwrap();
+        let mut t = Envelope {
+            tenant_id: Some("acme".to_string()),
+            ..Telemetry::new("d1", timestamp, 1.5, 50.0, 90.0).into()
+        };
+
+        let mut buf = Vec::new();
+        encode(&t, 7, &mut buf);
+
+        let mut expected = vec![0, 0, 0, 0, 7];
+        expected.extend_from_slice(&[8, b'a', b'c', b'm', b'e', 4, b'd', b'1']);
+        write_long(&mut expected, 1_700_000_000_000_000);
+        expected.extend_from_slice(&1.5f64.to_le_bytes());
+        expected.extend_from_slice(&50.0f64.to_le_bytes());
+        expected.extend_from_slice(&90.0f64.to_le_bytes());
+        expected.push(0);
+        assert_eq!(buf, expected);
+
+        t.received_at = Some(timestamp);
+        let mut with_arrival = Vec::new();
+        encode(&t, 7, &mut with_arrival);
+        assert_eq!(with_arrival[..expected.len() - 1], expected[..expected.len() - 1]);
+        assert_eq!(with_arrival[expected.len() - 1], 2);
+    }
+
+    #[tokio::test]
+    async fn test_register() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/registry/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 4096];
+            while !String::from_utf8_lossy(&request).contains("\"}") {
+                let n = stream.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\nConnection: close\r\n\r\n{\"id\":42}")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        assert_eq!(register(&url, "telemetry-value").await.unwrap(), 42);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /registry/subjects/telemetry-value/versions "));
+        assert!(request.contains("content-type: application/vnd.schemaregistry.v1+json\r\n"));
+        let body: serde_json::Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["schema"], SCHEMA);
+    }
+
+    #[test]
+    fn test_schema_is_valid_json() {
+        let schema: serde_json::Value = serde_json::from_str(SCHEMA).unwrap();
+        assert_eq!(schema["fields"].as_array().unwrap().len(), 7);
+    }
+}
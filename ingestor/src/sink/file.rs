@@ -0,0 +1,457 @@
+use super::{ExportRecord, TelemetrySink};
+use crate::db::tenant_of;
+use crate::errors::Result;
+use crate::model::{Envelope, Telemetry};
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+const PARQUET_SCHEMA: &str = "
+    message telemetry {
+        REQUIRED BYTE_ARRAY tenant_id (STRING);
+        REQUIRED BYTE_ARRAY device_id (STRING);
+        REQUIRED INT64 ts (TIMESTAMP(MICROS, true));
+        REQUIRED DOUBLE temperature;
+        REQUIRED DOUBLE humidity;
+        REQUIRED DOUBLE battery;
+        OPTIONAL INT64 received_at (TIMESTAMP(MICROS, true));
+    }";
+
+/// Suffix of a file still being written; landing-zone readers should skip it
+const IN_PROGRESS: &str = "inprogress";
+
+/// Suffix of a file that could not be completed, kept for recovery
+const FAILED: &str = "failed";
+
+/// How often an idle file is checked for rotation
+const ROTATE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    /// One JSON object per line
+    Ndjson,
+    /// Snappy-compressed Parquet, one row group per batch
+    Parquet,
+}
+
+impl FileFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Ndjson => "ndjson",
+            FileFormat::Parquet => "parquet",
+        }
+    }
+}
+
+enum Writer {
+    /// `len` covers the batches written whole so far
+    Ndjson { file: File, len: u64 },
+    Parquet(SerializedFileWriter<File>),
+}
+
+struct OpenFile {
+    path: PathBuf,
+    opened: Instant,
+    writer: Writer,
+}
+
+impl OpenFile {
+    fn bytes_written(&self) -> u64 {
+        match &self.writer {
+            Writer::Ndjson { len, .. } => *len,
+            Writer::Parquet(writer) => writer.bytes_written() as u64,
+        }
+    }
+
+    fn due(&self, rotate_after: Duration, rotate_bytes: u64) -> bool {
+        self.opened.elapsed() >= rotate_after || self.bytes_written() >= rotate_bytes
+    }
+
+    fn write(&mut self, batch: &[Envelope]) -> Result<()> {
+        match &mut self.writer {
+            Writer::Ndjson { file, len } => {
+                let mut lines = Vec::new();
+                for t in batch {
+                    serde_json::to_writer(&mut lines, &ExportRecord::from(t))?;
+                    lines.push(b'\n');
+                }
+                file.write_all(&lines)?;
+                *len += lines.len() as u64;
+                Ok(())
+            }
+            Writer::Parquet(writer) => Ok(write_row_group(writer, batch).map_err(io_error)?),
+        }
+    }
+
+    /// Cuts a failed batch off the end of the file again. False when the file
+    /// cannot take more batches, as after a failed Parquet row group.
+    fn roll_back(&mut self) -> bool {
+        let Writer::Ndjson { file, len } = &mut self.writer else {
+            return false;
+        };
+        match file.set_len(*len).and_then(|_| file.seek(SeekFrom::Start(*len))) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Failed to roll back {}: {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Completes the file and drops the in-progress suffix. A file that
+    /// cannot be completed is set aside instead.
+    fn finish(self) -> Result<PathBuf> {
+        let closed = match self.writer {
+            Writer::Ndjson { file, .. } => file.sync_all(),
+            Writer::Parquet(writer) => writer.close().map(|_| ()).map_err(io_error),
+        };
+        if let Err(e) = closed {
+            set_aside(&self.path);
+            return Err(e.into());
+        }
+        let done = self.path.with_extension("");
+        fs::rename(&self.path, &done)?;
+        Ok(done)
+    }
+}
+
+/// Renames an in-progress file to `.failed`, keeping the readings already
+/// acknowledged from it on disk for recovery
+fn set_aside(path: &Path) {
+    let failed = path.with_extension(FAILED);
+    match fs::rename(path, &failed) {
+        Ok(()) => error!("Set aside {} after a failed write", failed.display()),
+        Err(e) => error!("Failed to set aside {}: {}", path.display(), e),
+    }
+}
+
+fn write_row_group(writer: &mut SerializedFileWriter<File>, batch: &[Envelope]) -> parquet::errors::Result<()> {
+    let strings = |f: fn(&Envelope) -> &str| -> Vec<ByteArray> { batch.iter().map(|t| f(t).into()).collect() };
+    let doubles = |f: fn(&Telemetry) -> f64| -> Vec<f64> { batch.iter().map(|t| f(&t.telemetry)).collect() };
+    let received: Vec<i64> = batch.iter().filter_map(|t| t.received_at).map(|r| r.timestamp_micros()).collect();
+    let received_levels: Vec<i16> = batch.iter().map(|t| t.received_at.is_some() as i16).collect();
+
+    let mut row_group = writer.next_row_group()?;
+    let mut column = 0;
+    while let Some(mut writer) = row_group.next_column()? {
+        match column {
+            0 => writer.typed::<ByteArrayType>().write_batch(&strings(tenant_of), None, None)?,
+            1 => writer.typed::<ByteArrayType>().write_batch(&strings(|t| &t.telemetry.device_id), None, None)?,
+            2 => {
+                let ts: Vec<i64> = batch.iter().map(|t| t.telemetry.timestamp.timestamp_micros()).collect();
+                writer.typed::<Int64Type>().write_batch(&ts, None, None)?
+            }
+            3 => writer.typed::<DoubleType>().write_batch(&doubles(|t| t.temperature), None, None)?,
+            4 => writer.typed::<DoubleType>().write_batch(&doubles(|t| t.humidity), None, None)?,
+            5 => writer.typed::<DoubleType>().write_batch(&doubles(|t| t.battery), None, None)?,
+            _ => writer
+                .typed::<Int64Type>()
+                .write_batch(&received, Some(&received_levels), None)?,
+        };
+        writer.close()?;
+        column += 1;
+    }
+    row_group.close()?;
+    Ok(())
+}
+
+fn io_error(e: parquet::errors::ParquetError) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+/// Rotating NDJSON or Parquet files for data-lake landing.
+///
+/// Files are written as `<dir>/telemetry-<opened at>-<n>.<ext>.inprogress`
+/// and renamed to `.<ext>` once complete, so readers only pick up whole files.
+/// Rotation is checked before each batch, every second while idle, and at
+/// shutdown. A failed NDJSON batch is cut off the file again before the
+/// retry; a file that cannot continue is renamed to `.<ext>.failed`.
+pub struct FileSink {
+    dir: PathBuf,
+    format: FileFormat,
+    rotate_after: Duration,
+    rotate_bytes: u64,
+    state: Arc<Mutex<FileState>>,
+    rotation: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct FileState {
+    current: Option<OpenFile>,
+    opened_total: u64,
+}
+
+impl FileState {
+    /// Completes the current file once it is old or big enough
+    fn rotate_if_due(&mut self, rotate_after: Duration, rotate_bytes: u64) -> Result<()> {
+        if self.current.as_ref().is_some_and(|file| file.due(rotate_after, rotate_bytes)) {
+            let done = self.current.take().unwrap().finish()?;
+            info!("Rotated {}", done.display());
+        }
+        Ok(())
+    }
+}
+
+impl FileSink {
+    pub async fn open(dir: impl AsRef<Path>, format: FileFormat, rotate_secs: u64, rotate_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        let rotate_after = Duration::from_secs(rotate_secs.max(1));
+        let rotate_bytes = rotate_bytes.max(1);
+        let state = Arc::new(Mutex::new(FileState::default()));
+        let rotation = tokio::spawn(run_rotation(state.clone(), rotate_after, rotate_bytes));
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            format,
+            rotate_after,
+            rotate_bytes,
+            state,
+            rotation,
+        })
+    }
+
+    fn new_file(&self, opened_total: u64) -> Result<OpenFile> {
+        let name = format!(
+            "telemetry-{}-{}.{}.{}",
+            Utc::now().format("%Y%m%dT%H%M%S%.6fZ"),
+            opened_total,
+            self.format.extension(),
+            IN_PROGRESS
+        );
+        let path = self.dir.join(name);
+        let file = File::create(&path)?;
+        let writer = match self.format {
+            FileFormat::Ndjson => Writer::Ndjson { file, len: 0 },
+            FileFormat::Parquet => {
+                let schema = Arc::new(parse_message_type(PARQUET_SCHEMA).map_err(io_error)?);
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Writer::Parquet(SerializedFileWriter::new(file, schema, Arc::new(properties)).map_err(io_error)?)
+            }
+        };
+        Ok(OpenFile {
+            path,
+            opened: Instant::now(),
+            writer,
+        })
+    }
+
+    fn write_sync(&self, batch: &[Envelope]) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        state.rotate_if_due(self.rotate_after, self.rotate_bytes)?;
+        if state.current.is_none() {
+            let file = self.new_file(state.opened_total)?;
+            state.opened_total += 1;
+            state.current = Some(file);
+        }
+
+        let current = state.current.as_mut().unwrap();
+        if let Err(e) = current.write(batch) {
+            // The earlier batches in the file are acknowledged, so it is never
+            // removed; the retry goes to this file or, if set aside, a new one
+            if !current.roll_back() {
+                let path = state.current.take().unwrap().path;
+                set_aside(&path);
+            }
+            return Err(e);
+        }
+        Ok(batch.len() as u64)
+    }
+}
+
+/// Completes files that stay open past `rotate_after` without new batches
+async fn run_rotation(state: Arc<Mutex<FileState>>, rotate_after: Duration, rotate_bytes: u64) {
+    let mut ticker = tokio::time::interval(ROTATE_CHECK_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        if let Err(e) = state.lock().unwrap().rotate_if_due(rotate_after, rotate_bytes) {
+            warn!("Failed to rotate file: {}", e);
+        }
+    }
+}
+
+impl TelemetrySink for FileSink {
+    fn name(&self) -> &str {
+        self.format.extension()
+    }
+
+    fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(async move { self.write_sync(batch) })
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.rotation.abort();
+            if let Some(file) = self.state.lock().unwrap().current.take() {
+                let done = file.finish()?;
+                info!("Closed {}", done.display());
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        self.rotation.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    fn batch(from: usize, len: usize) -> Vec<Envelope> {
+        (from..from + len)
+            .map(|i| Envelope {
+                tenant_id: Some("acme".to_string()),
+                received_at: (i % 2 == 0).then(Utc::now),
+                ..Telemetry::new(format!("dev-{}", i), Utc::now(), i as f64, 50.0, 90.0).into()
+            })
+            .collect()
+    }
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("sink-test-{}", uuid::Uuid::new_v4()));
+        let sink = FileSink::open(&dir, FileFormat::Ndjson, 3600, 1).await.unwrap();
+
+        assert_eq!(sink.write(&batch(0, 2)).await.unwrap(), 2);
+        let open = files(&dir);
+        assert_eq!(open.len(), 1);
+        assert!(open[0].to_str().unwrap().ends_with(".ndjson.inprogress"));
+
+        // Over the size limit, so the next batch starts a new file
+        sink.write(&batch(2, 1)).await.unwrap();
+        sink.close().await.unwrap();
+        let done = files(&dir);
+        assert_eq!(done.len(), 2);
+        assert!(done.iter().all(|p| p.extension().unwrap() == "ndjson"));
+
+        let first = fs::read_to_string(&done[0]).unwrap();
+        let lines: Vec<serde_json::Value> = first.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["device_id"], "dev-1");
+        assert_eq!(lines[1]["tenant_id"], "acme");
+        assert_eq!(lines[1]["received_at"], serde_json::Value::Null);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn lines(path: &Path) -> Vec<serde_json::Value> {
+        let content = fs::read_to_string(path).unwrap();
+        content.lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_failed_ndjson_batch_is_rolled_back() {
+        let dir = std::env::temp_dir().join(format!("sink-test-{}", uuid::Uuid::new_v4()));
+        let sink = FileSink::open(&dir, FileFormat::Ndjson, 3600, u64::MAX).await.unwrap();
+        sink.write(&batch(0, 2)).await.unwrap();
+
+        // A batch cut short mid-line, as a full disk leaves it
+        {
+            let mut state = sink.state.lock().unwrap();
+            let current = state.current.as_mut().unwrap();
+            let Writer::Ndjson { file, .. } = &mut current.writer else { unreachable!() };
+            file.write_all(b"{\"tenant_id\":\"ac").unwrap();
+            assert!(current.roll_back());
+        }
+        sink.write(&batch(2, 1)).await.unwrap();
+        sink.close().await.unwrap();
+
+        let done = files(&dir);
+        assert_eq!(done.len(), 1);
+        let devices: Vec<_> = lines(&done[0]).iter().map(|l| l["device_id"].clone()).collect();
+        assert_eq!(devices, ["dev-0", "dev-1", "dev-2"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_file_is_set_aside() {
+        let dir = std::env::temp_dir().join(format!("sink-test-{}", uuid::Uuid::new_v4()));
+        let sink = FileSink::open(&dir, FileFormat::Ndjson, 3600, u64::MAX).await.unwrap();
+        sink.write(&batch(0, 2)).await.unwrap();
+
+        // A read-only handle fails both the write and the roll back
+        {
+            let mut state = sink.state.lock().unwrap();
+            let current = state.current.as_mut().unwrap();
+            let Writer::Ndjson { file, .. } = &mut current.writer else { unreachable!() };
+            *file = File::open(&current.path).unwrap();
+        }
+        assert!(sink.write(&batch(2, 1)).await.is_err());
+        let failed = files(&dir);
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].to_str().unwrap().ends_with(".ndjson.failed"));
+        assert_eq!(lines(&failed[0]).len(), 2);
+
+        // The retry starts a new file
+        sink.write(&batch(2, 1)).await.unwrap();
+        sink.close().await.unwrap();
+        let all = files(&dir);
+        assert_eq!(all.len(), 2);
+        assert_eq!(lines(&all[1])[0]["device_id"], "dev-2");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_file_rotates() {
+        let dir = std::env::temp_dir().join(format!("sink-test-{}", uuid::Uuid::new_v4()));
+        let sink = FileSink::open(&dir, FileFormat::Ndjson, 1, u64::MAX).await.unwrap();
+        sink.write(&batch(0, 1)).await.unwrap();
+
+        // Completed by the timer, with no further batch or close
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let done = files(&dir);
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].extension().unwrap(), "ndjson");
+        drop(sink);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_parquet_round_trip() {
+        let dir = std::env::temp_dir().join(format!("sink-test-{}", uuid::Uuid::new_v4()));
+        let sink = FileSink::open(&dir, FileFormat::Parquet, 3600, u64::MAX).await.unwrap();
+        sink.write(&batch(0, 3)).await.unwrap();
+        sink.write(&batch(3, 2)).await.unwrap();
+        sink.close().await.unwrap();
+
+        let done = files(&dir);
+        assert_eq!(done.len(), 1);
+        let reader = SerializedFileReader::new(File::open(&done[0]).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), 5);
+
+        let columns: Vec<(&String, &Field)> = rows[4].get_column_iter().collect();
+        assert_eq!(columns[0], (&"tenant_id".to_string(), &Field::Str("acme".to_string())));
+        assert_eq!(columns[1].1, &Field::Str("dev-4".to_string()));
+        assert_eq!(columns[3].1, &Field::Double(4.0));
+        assert!(matches!(columns[6].1, Field::TimestampMicros(_)));
+        assert_eq!(rows[3].get_column_iter().nth(6).unwrap().1, &Field::Null);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+use super::avro;
+use super::{ExportRecord, TelemetrySink};
+use crate::errors::{Error, Result};
+use crate::metrics::{KAFKA_ACK_LATENCY_SECONDS, KAFKA_IN_FLIGHT, KAFKA_RECORDS_TOTAL};
+use crate::model::Envelope;
+use crate::spool::Spool;
+use futures_util::future::{join_all, BoxFuture};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+const CLIENT_ID: &str = "ingestor";
+
+/// Produce timeouts a record may take, retries included, before it is spooled
+const DELIVERY_ATTEMPTS: u64 = 2;
+
+/// How often spooled records are offered to the broker again
+const SPOOL_REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaFormat {
+    /// The same JSON object as an NDJSON file line
+    Json,
+    /// Avro in the Confluent framing: magic byte, schema id, binary record
+    Avro,
+}
+
+/// Which replicas must have a record before the broker acknowledges it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Acks {
+    Leader,
+    All,
+}
+
+impl Acks {
+    fn setting(self) -> &'static str {
+        match self {
+            Acks::Leader => "1",
+            Acks::All => "all",
+        }
+    }
+}
+
+/// Options of the `kafka` sink in `SINKS_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+pub struct KafkaConfig {
+    /// Bootstrap brokers as `host:port`; the rest are found through metadata
+    pub brokers: Vec<String>,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    #[serde(default = "default_format")]
+    pub format: KafkaFormat,
+    #[serde(default = "default_acks")]
+    pub acks: Acks,
+    /// How long the broker may wait for replicas before failing a produce;
+    /// records not acknowledged within twice this are spooled
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Largest record batch sent for one partition in one request
+    #[serde(default = "default_max_batch_bytes")]
+    pub max_batch_bytes: usize,
+    /// Registers the Avro schema here on startup; without it values carry `schema_id`
+    #[serde(default)]
+    pub schema_registry_url: Option<String>,
+    #[serde(default = "default_schema_id")]
+    pub schema_id: i32,
+    /// Records the broker did not acknowledge wait here for replay
+    #[serde(default = "default_spool_dir")]
+    pub spool_dir: String,
+}
+
+fn default_topic() -> String {
+    "telemetry".to_string()
+}
+
+fn default_format() -> KafkaFormat {
+    KafkaFormat::Json
+}
+
+fn default_acks() -> Acks {
+    Acks::All
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_max_batch_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_schema_id() -> i32 {
+    1
+}
+
+fn default_spool_dir() -> String {
+    "spool".to_string()
+}
+
+/// Forwards readings to a Kafka topic, or anything speaking its protocol
+/// such as Redpanda, keyed by device id.
+///
+/// Records are produced with librdkafka and partitioned like the Java
+/// client's default partitioner, so a device's readings stay in order on one
+/// partition. Those not acknowledged within the delivery timeout are appended
+/// to a spool under `spool_dir` and replayed every second, after newer
+/// readings, until the broker takes them.
+pub struct KafkaSink {
+    producer: Arc<Producer>,
+    spool: Arc<Spool>,
+    replay: JoinHandle<()>,
+}
+
+impl KafkaSink {
+    /// Registers the Avro schema if configured; brokers are only contacted on the first write
+    pub async fn open(config: &KafkaConfig) -> Result<Self> {
+        if config.brokers.is_empty() {
+            return Err(Error::Validation("kafka sink needs at least one broker".to_string()));
+        }
+        let encoding = match config.format {
+            KafkaFormat::Json => Encoding::Json,
+            KafkaFormat::Avro => {
+                let schema_id = match &config.schema_registry_url {
+                    Some(url) => avro::register(url, &format!("{}-value", config.topic)).await?,
+                    None => config.schema_id,
+                };
+                info!("Encoding Kafka values with Avro schema id {}", schema_id);
+                Encoding::Avro { schema_id }
+            }
+        };
+
+        let producer = Arc::new(Producer::new(config, encoding)?);
+        let spool = Arc::new(Spool::open(&config.spool_dir, &format!("kafka-{}", config.topic))?);
+        let replay = tokio::spawn(run_spool_replay(producer.clone(), spool.clone()));
+        Ok(Self {
+            producer,
+            spool,
+            replay,
+        })
+    }
+}
+
+impl TelemetrySink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    /// Counts acknowledged records only; spooled ones show up in
+    /// `ingestor_kafka_records_total` once replayed. Never fails, since a
+    /// retry would produce the acknowledged records again: readings that
+    /// cannot be spooled either are logged and dropped.
+    fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(async move {
+            let topic = &self.producer.topic;
+            let undelivered = self.producer.deliver(batch).await;
+            let acked = batch.len() - undelivered.len();
+            KAFKA_RECORDS_TOTAL
+                .with_label_values(&[topic, "acked"])
+                .inc_by(acked as f64);
+
+            if !undelivered.is_empty() {
+                warn!(
+                    "Spooling {} records the broker did not acknowledge for topic {}",
+                    undelivered.len(),
+                    topic
+                );
+                let errors: Vec<Error> = undelivered
+                    .iter()
+                    .filter_map(|envelope| self.spool.append(envelope).err())
+                    .collect();
+                KAFKA_RECORDS_TOTAL
+                    .with_label_values(&[topic, "spooled"])
+                    .inc_by((undelivered.len() - errors.len()) as f64);
+                if let Some(e) = errors.first() {
+                    error!(
+                        "CRITICAL: {} records for topic {} could not be spooled and are dropped: {}",
+                        errors.len(),
+                        topic,
+                        e
+                    );
+                }
+            }
+            Ok(acked as u64)
+        })
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async {
+            self.replay.abort();
+            Ok(())
+        })
+    }
+}
+
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        self.replay.abort();
+    }
+}
+
+/// Offers spooled records to the broker again, oldest file first
+async fn run_spool_replay(producer: Arc<Producer>, spool: Arc<Spool>) {
+    let mut ticker = tokio::time::interval(SPOOL_REPLAY_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+        let files = match spool.take() {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Failed to read Kafka spool for topic {}: {}", producer.topic, e);
+                continue;
+            }
+        };
+
+        for path in files {
+            let records = match Spool::read(&path) {
+                Ok(records) => records,
+                Err(e) => {
+                    warn!("Failed to read spool file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            let undelivered = producer.deliver(&records).await;
+            if !records.is_empty() && undelivered.len() == records.len() {
+                // Still unavailable; the file is taken again on the next tick
+                break;
+            }
+            let replayed = records.len() - undelivered.len();
+            info!(
+                "Replayed {} spooled records to topic {}",
+                replayed, producer.topic
+            );
+            KAFKA_RECORDS_TOTAL
+                .with_label_values(&[&producer.topic, "replayed"])
+                .inc_by(replayed as f64);
+
+            // A partly delivered file keeps only its remainder
+            if let Err(e) = undelivered.iter().try_for_each(|t| spool.append(t)) {
+                warn!("Failed to respool Kafka records: {}", e);
+                continue;
+            }
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove spool file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+enum Encoding {
+    Json,
+    Avro { schema_id: i32 },
+}
+
+/// A librdkafka producer for one topic
+struct Producer {
+    producer: FutureProducer,
+    topic: String,
+    encoding: Encoding,
+}
+
+impl Producer {
+    fn new(config: &KafkaConfig, encoding: Encoding) -> Result<Self> {
+        let timeout_ms = config.timeout_ms.clamp(1, i32::MAX as u64 / DELIVERY_ATTEMPTS);
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", config.brokers.join(","))
+            .set("client.id", CLIENT_ID)
+            .set("acks", config.acks.setting())
+            .set("request.timeout.ms", timeout_ms.to_string())
+            .set("message.timeout.ms", (timeout_ms * DELIVERY_ATTEMPTS).to_string())
+            .set("batch.size", config.max_batch_bytes.clamp(1, i32::MAX as usize).to_string())
+            // Hashes keys like the Java client
+            .set("partitioner", "murmur2_random");
+        // Retried batches must not overtake later ones on their partition
+        match config.acks {
+            Acks::All => client.set("enable.idempotence", "true"),
+            Acks::Leader => client.set("max.in.flight.requests.per.connection", "1"),
+        };
+        let producer = client.create().map_err(|e| Error::Kafka(e.to_string()))?;
+        Ok(Self {
+            producer,
+            topic: config.topic.clone(),
+            encoding,
+        })
+    }
+
+    /// Produces `batch` and returns the readings the broker did not
+    /// acknowledge within the delivery timeout
+    async fn deliver<'a>(&self, batch: &'a [Envelope]) -> Vec<&'a Envelope> {
+        let values: Vec<Vec<u8>> = batch.iter().map(|t| self.encode(t)).collect();
+        let in_flight = KAFKA_IN_FLIGHT.with_label_values(&[&self.topic]);
+        let latency = KAFKA_ACK_LATENCY_SECONDS.with_label_values(&[&self.topic]);
+        let started = Instant::now();
+
+        // Queued one by one, so each partition receives them in batch order
+        let deliveries: Vec<_> = batch
+            .iter()
+            .zip(&values)
+            .map(|(t, value)| {
+                let record = FutureRecord::to(&self.topic)
+                    .key(&t.telemetry.device_id)
+                    .payload(value)
+                    .timestamp(t.telemetry.timestamp.timestamp_millis());
+                self.producer.send_result(record).map_err(|(e, _)| e.to_string())
+            })
+            .collect();
+        in_flight.add(deliveries.iter().filter(|d| d.is_ok()).count() as f64);
+
+        let results = join_all(deliveries.into_iter().map(|delivery| async {
+            let result = delivery?.await;
+            in_flight.dec();
+            match result {
+                Ok(Ok(_)) => {
+                    latency.observe(started.elapsed().as_secs_f64());
+                    Ok(())
+                }
+                Ok(Err((e, _))) => Err(e.to_string()),
+                Err(_) => Err("producer shut down".to_string()),
+            }
+        }))
+        .await;
+
+        let mut undelivered = Vec::new();
+        let mut first_error = None;
+        for (t, result) in batch.iter().zip(results) {
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+                undelivered.push(t);
+            }
+        }
+        if let Some(e) = first_error {
+            warn!(
+                "Broker did not acknowledge {} records on topic {}: {}",
+                undelivered.len(),
+                self.topic,
+                e
+            );
+        }
+        undelivered
+    }
+
+    fn encode(&self, t: &Envelope) -> Vec<u8> {
+        match self.encoding {
+            Encoding::Json => serde_json::to_vec(&ExportRecord::from(t)).unwrap(),
+            Encoding::Avro { schema_id } => {
+                let mut value = Vec::new();
+                avro::encode(t, schema_id, &mut value);
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Telemetry;
+    use chrono::Utc;
+    use rdkafka::consumer::{Consumer, StreamConsumer};
+    use rdkafka::Message;
+    use std::collections::HashMap;
+
+    fn config(brokers: Vec<String>, spool_dir: &std::path::Path) -> KafkaConfig {
+        serde_json::from_value(serde_json::json!({
+            "brokers": brokers,
+            "spool_dir": spool_dir,
+            "timeout_ms": 200,
+        }))
+        .unwrap()
+    }
+
+    fn readings(devices: usize, per_device: usize) -> Vec<Envelope> {
+        (0..devices * per_device)
+            .map(|i| Envelope {
+                tenant_id: Some("acme".to_string()),
+                ..Telemetry::new(format!("dev-{}", i % devices), Utc::now(), i as f64, 50.0, 90.0).into()
+            })
+            .collect()
+    }
+
+    fn spooled(dir: &std::path::Path, topic: &str) -> usize {
+        let spool = Spool::open(dir, &format!("kafka-{}", topic)).unwrap();
+        spool.take().unwrap().iter().map(|p| Spool::read(p).unwrap().len()).sum()
+    }
+
+    #[tokio::test]
+    async fn test_spools_when_unreachable() {
+        let dir = std::env::temp_dir().join(format!("kafka-test-{}", uuid::Uuid::new_v4()));
+        let sink = KafkaSink::open(&config(vec!["127.0.0.1:1".to_string()], &dir)).await.unwrap();
+
+        assert_eq!(sink.write(&readings(2, 1)).await.unwrap(), 0);
+        sink.close().await.unwrap();
+        assert_eq!(spooled(&dir, "telemetry"), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_spool_is_not_a_write_error() {
+        let dir = std::env::temp_dir().join(format!("kafka-test-{}", uuid::Uuid::new_v4()));
+        let sink = KafkaSink::open(&config(vec!["127.0.0.1:1".to_string()], &dir)).await.unwrap();
+        sink.close().await.unwrap();
+        // A file where the spool directory was makes every append fail
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::write(&dir, b"").unwrap();
+
+        assert_eq!(sink.write(&readings(2, 1)).await.unwrap(), 0);
+        std::fs::remove_file(&dir).unwrap();
+    }
+
+    /// Against a real broker, e.g. a single-node Redpanda:
+    /// `KAFKA_BROKERS=localhost:9092 cargo test -p ingestor redpanda -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a Kafka broker in KAFKA_BROKERS"]
+    async fn test_redpanda() {
+        let brokers = std::env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS not set");
+        let dir = std::env::temp_dir().join(format!("kafka-test-{}", uuid::Uuid::new_v4()));
+        let mut config = config(brokers.split(',').map(str::to_string).collect(), &dir);
+        config.topic = format!("ingestor-test-{}", uuid::Uuid::new_v4());
+        config.timeout_ms = 5000;
+        let sink = KafkaSink::open(&config).await.unwrap();
+
+        assert_eq!(sink.write(&readings(5, 20)).await.unwrap(), 100);
+        sink.close().await.unwrap();
+        assert_eq!(spooled(&dir, &config.topic), 0);
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", &config.topic)
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&[&config.topic]).unwrap();
+        let mut by_device: HashMap<Vec<u8>, Vec<(i32, f64)>> = HashMap::new();
+        for _ in 0..100 {
+            let message = tokio::time::timeout(Duration::from_secs(30), consumer.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let value: serde_json::Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+            assert_eq!(value["tenant_id"], "acme");
+            assert_eq!(value["device_id"].as_str().unwrap().as_bytes(), message.key().unwrap());
+            by_device
+                .entry(message.key().unwrap().to_vec())
+                .or_default()
+                .push((message.partition(), value["temperature"].as_f64().unwrap()));
+        }
+        // Each device's readings are on one partition, in the order written
+        let dev_0 = &by_device[&b"dev-0".to_vec()];
+        assert!(dev_0.iter().all(|(partition, _)| *partition == dev_0[0].0));
+        let temperatures: Vec<f64> = dev_0.iter().map(|(_, t)| *t).collect();
+        assert_eq!(temperatures, (0..20).map(|i| i as f64 * 5.0).collect::<Vec<_>>());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
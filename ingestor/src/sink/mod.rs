@@ -0,0 +1,246 @@
+mod avro;
+mod file;
+mod kafka;
+mod postgres;
+mod sqlite;
+
+pub use file::{FileFormat, FileSink};
+pub use kafka::{Acks, KafkaConfig, KafkaFormat, KafkaSink};
+pub use postgres::{PostgresSink, TimescaleSink};
+pub use sqlite::SqliteSink;
+
+use crate::db::{tenant_of, ConflictPolicy, WriteStrategy};
+use crate::errors::{Error, Result};
+use crate::model::Envelope;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+/// Storage the batcher writes to
+pub trait TelemetrySink: Send + Sync {
+    /// Label in logs and the `sink` metric label
+    fn name(&self) -> &str;
+
+    /// Writes a batch and returns the number of rows stored. A failed batch
+    /// is retried whole, so writes should be idempotent where possible.
+    fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>>;
+
+    /// Finishes buffered output at shutdown, e.g. the file being written
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A reading as exported to files and logs; unlike the wire format it keeps
+/// the tenant and arrival time
+#[derive(Debug, Serialize)]
+pub(crate) struct ExportRecord<'a> {
+    tenant_id: &'a str,
+    device_id: &'a str,
+    timestamp: DateTime<Utc>,
+    temperature: f64,
+    humidity: f64,
+    battery: f64,
+    received_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a Envelope> for ExportRecord<'a> {
+    fn from(envelope: &'a Envelope) -> Self {
+        let t = &envelope.telemetry;
+        Self {
+            tenant_id: tenant_of(envelope),
+            device_id: &t.device_id,
+            timestamp: t.timestamp,
+            temperature: t.temperature,
+            humidity: t.humidity,
+            battery: t.battery,
+            received_at: envelope.received_at,
+        }
+    }
+}
+
+/// One storage backend in `SINKS_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// The `telemetry` table, in `DATABASE_URL` unless `url` is set
+    Postgres {
+        #[serde(default)]
+        url: Option<String>,
+    },
+    /// Like `postgres`, with `telemetry` turned into a compressed hypertable
+    Timescale {
+        #[serde(default)]
+        url: Option<String>,
+        #[serde(default = "default_chunk_interval")]
+        chunk_interval: String,
+        /// Chunks older than this are compressed
+        #[serde(default = "default_compress_after")]
+        compress_after: String,
+        #[serde(default = "default_segment_by")]
+        segment_by: Vec<String>,
+    },
+    /// A local SQLite database file, created if missing
+    Sqlite { path: String },
+    /// Rotating files for data-lake landing
+    File {
+        dir: String,
+        #[serde(default = "default_file_format")]
+        format: FileFormat,
+        /// Start a new file once the current one is this old
+        #[serde(default = "default_rotate_secs")]
+        rotate_secs: u64,
+        /// Start a new file once the current one is this large
+        #[serde(default = "default_rotate_bytes")]
+        rotate_bytes: u64,
+    },
+    /// A Kafka-protocol topic, keyed by device id
+    Kafka(KafkaConfig),
+}
+
+fn default_chunk_interval() -> String {
+    "1 day".to_string()
+}
+
+fn default_compress_after() -> String {
+    "7 days".to_string()
+}
+
+fn default_segment_by() -> Vec<String> {
+    vec!["tenant_id".to_string(), "device_id".to_string()]
+}
+
+fn default_file_format() -> FileFormat {
+    FileFormat::Ndjson
+}
+
+fn default_rotate_secs() -> u64 {
+    3600
+}
+
+fn default_rotate_bytes() -> u64 {
+    128 * 1024 * 1024
+}
+
+/// Sinks every batch is written to, loaded from the JSON file named by `SINKS_CONFIG`.
+///
+/// The first sink is the primary: the row accounting metrics follow it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinksConfig {
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            sinks: vec![SinkConfig::Postgres { url: None }],
+        }
+    }
+}
+
+impl SinksConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&contents)?;
+        if config.sinks.is_empty() {
+            return Err(Error::Validation("no sinks configured".to_string()));
+        }
+        Ok(config)
+    }
+
+    /// Opens every sink. Postgres-backed sinks without a `url` share `pool`;
+    /// `strategy` applies to them and `policy` to every database sink.
+    pub async fn open(
+        &self,
+        pool: &PgPool,
+        strategy: WriteStrategy,
+        policy: ConflictPolicy,
+    ) -> Result<Vec<Arc<dyn TelemetrySink>>> {
+        let mut sinks: Vec<Arc<dyn TelemetrySink>> = Vec::with_capacity(self.sinks.len());
+        for config in &self.sinks {
+            let sink: Arc<dyn TelemetrySink> = match config {
+                SinkConfig::Postgres { url } => {
+                    let pool = pool_for(pool, url.as_deref()).await?;
+                    Arc::new(PostgresSink::new(pool, strategy, policy))
+                }
+                SinkConfig::Timescale {
+                    url,
+                    chunk_interval,
+                    compress_after,
+                    segment_by,
+                } => {
+                    let pool = pool_for(pool, url.as_deref()).await?;
+                    let sink = TimescaleSink::new(
+                        PostgresSink::new(pool, strategy, policy),
+                        chunk_interval,
+                        compress_after,
+                        segment_by,
+                    )?;
+                    sink.setup().await?;
+                    Arc::new(sink)
+                }
+                SinkConfig::Sqlite { path } => Arc::new(SqliteSink::open(path, policy).await?),
+                SinkConfig::File {
+                    dir,
+                    format,
+                    rotate_secs,
+                    rotate_bytes,
+                } => Arc::new(FileSink::open(dir, *format, *rotate_secs, *rotate_bytes).await?),
+                SinkConfig::Kafka(config) => Arc::new(KafkaSink::open(config).await?),
+            };
+            info!("Opened {} sink", sink.name());
+            sinks.push(sink);
+        }
+        Ok(sinks)
+    }
+}
+
+async fn pool_for(pool: &PgPool, url: Option<&str>) -> Result<PgPool> {
+    match url {
+        Some(url) => crate::db::make_pool(url).await,
+        None => Ok(pool.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sinks_config() {
+        let config: SinksConfig = serde_json::from_str(
+            r#"{ "sinks": [
+                { "type": "postgres" },
+                { "type": "timescale", "compress_after": "2 days" },
+                { "type": "sqlite", "path": "edge.db" },
+                { "type": "file", "dir": "landing", "format": "parquet", "rotate_secs": 60 },
+                { "type": "kafka", "brokers": ["localhost:9092"], "format": "avro", "acks": "leader" }
+            ] }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(config.sinks[0], SinkConfig::Postgres { url: None }));
+        assert!(matches!(
+            &config.sinks[1],
+            SinkConfig::Timescale { chunk_interval, compress_after, segment_by, .. }
+                if chunk_interval == "1 day" && compress_after == "2 days" && segment_by.len() == 2
+        ));
+        assert!(matches!(&config.sinks[2], SinkConfig::Sqlite { path } if path == "edge.db"));
+        assert!(matches!(
+            &config.sinks[3],
+            SinkConfig::File { format: FileFormat::Parquet, rotate_secs: 60, rotate_bytes, .. }
+                if *rotate_bytes == default_rotate_bytes()
+        ));
+        assert!(matches!(
+            &config.sinks[4],
+            SinkConfig::Kafka(KafkaConfig { topic, format: KafkaFormat::Avro, acks: Acks::Leader, schema_registry_url: None, .. })
+                if topic == "telemetry"
+        ));
+
+        assert!(serde_json::from_str::<SinksConfig>(r#"{ "sinks": [{ "type": "mongo" }] }"#).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use super::TelemetrySink;
+use crate::db::{insert_batch, ConflictPolicy, WriteStrategy};
+use crate::errors::{Error, Result};
+use crate::model::Envelope;
+use futures_util::future::BoxFuture;
+use sqlx::PgPool;
+use tracing::info;
+
+/// Columns compressed chunks may be segmented by
+const SEGMENT_COLUMNS: [&str; 2] = ["tenant_id", "device_id"];
+
+/// The `telemetry` table written with [`insert_batch`]
+#[derive(Debug, Clone)]
+pub struct PostgresSink {
+    pool: PgPool,
+    strategy: WriteStrategy,
+    policy: ConflictPolicy,
+}
+
+impl PostgresSink {
+    pub fn new(pool: PgPool, strategy: WriteStrategy, policy: ConflictPolicy) -> Self {
+        Self {
+            pool,
+            strategy,
+            policy,
+        }
+    }
+}
+
+impl TelemetrySink for PostgresSink {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(insert_batch(&self.pool, batch, self.strategy, self.policy))
+    }
+}
+
+/// [`PostgresSink`] on a TimescaleDB hypertable with a compression policy.
+///
+/// `setup` converts `telemetry` in place the first time. Hypertable unique
+/// keys must include `ts`, so the `id` primary key becomes `(id, ts)`.
+#[derive(Debug, Clone)]
+pub struct TimescaleSink {
+    inner: PostgresSink,
+    chunk_interval: String,
+    compress_after: String,
+    segment_by: String,
+}
+
+impl TimescaleSink {
+    pub fn new(
+        inner: PostgresSink,
+        chunk_interval: &str,
+        compress_after: &str,
+        segment_by: &[String],
+    ) -> Result<Self> {
+        if let Some(column) = segment_by.iter().find(|c| !SEGMENT_COLUMNS.contains(&c.as_str())) {
+            return Err(Error::Validation(format!(
+                "cannot segment compressed chunks by {:?}, expected one of {:?}",
+                column, SEGMENT_COLUMNS
+            )));
+        }
+        Ok(Self {
+            inner,
+            chunk_interval: chunk_interval.to_string(),
+            compress_after: compress_after.to_string(),
+            segment_by: segment_by.join(", "),
+        })
+    }
+
+    /// Creates the hypertable and compression policy unless they exist
+    pub async fn setup(&self) -> Result<()> {
+        let pool = &self.inner.pool;
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS timescaledb")
+            .execute(pool)
+            .await?;
+
+        let compression_enabled: Option<bool> = sqlx::query_scalar(
+            "SELECT compression_enabled FROM timescaledb_information.hypertables \
+             WHERE hypertable_schema = current_schema() AND hypertable_name = 'telemetry'",
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if compression_enabled.is_none() {
+            info!(
+                "Converting telemetry to a hypertable with {} chunks",
+                self.chunk_interval
+            );
+            let mut tx = pool.begin().await?;
+            sqlx::query("ALTER TABLE telemetry DROP CONSTRAINT IF EXISTS telemetry_pkey")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("ALTER TABLE telemetry ADD PRIMARY KEY (id, ts)")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "SELECT create_hypertable('telemetry', 'ts', chunk_time_interval => $1::interval, \
+                 migrate_data => TRUE)",
+            )
+            .bind(&self.chunk_interval)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        }
+
+        if compression_enabled != Some(true) {
+            sqlx::query(&self.compress_sql()).execute(pool).await?;
+        }
+        sqlx::query(
+            "SELECT add_compression_policy('telemetry', compress_after => $1::interval, \
+             if_not_exists => TRUE)",
+        )
+        .bind(&self.compress_after)
+        .execute(pool)
+        .await?;
+
+        info!(
+            "Hypertable telemetry compresses chunks older than {}",
+            self.compress_after
+        );
+        Ok(())
+    }
+
+    /// Segment columns are checked in `new`, as they cannot be bound
+    fn compress_sql(&self) -> String {
+        format!(
+            "ALTER TABLE telemetry SET (timescaledb.compress, \
+             timescaledb.compress_segmentby = '{}', timescaledb.compress_orderby = 'ts DESC')",
+            self.segment_by
+        )
+    }
+}
+
+impl TelemetrySink for TimescaleSink {
+    fn name(&self) -> &str {
+        "timescale"
+    }
+
+    fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>> {
+        self.inner.write(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn postgres() -> PostgresSink {
+        let pool = PgPoolOptions::new().connect_lazy("postgres://localhost/unused").unwrap();
+        PostgresSink::new(pool, WriteStrategy::Unnest, ConflictPolicy::Ignore)
+    }
+
+    #[tokio::test]
+    async fn test_timescale_segment_by() {
+        let sink = TimescaleSink::new(postgres(), "1 day", "7 days", &["device_id".to_string()]).unwrap();
+        assert_eq!(
+            sink.compress_sql(),
+            "ALTER TABLE telemetry SET (timescaledb.compress, \
+             timescaledb.compress_segmentby = 'device_id', timescaledb.compress_orderby = 'ts DESC')"
+        );
+
+        let injected = ["device_id'); DROP TABLE telemetry; --".to_string()];
+        assert!(TimescaleSink::new(postgres(), "1 day", "7 days", &injected).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use super::TelemetrySink;
+use crate::db::{tenant_of, ConflictPolicy};
+use crate::errors::Result;
+use crate::model::Envelope;
+use futures_util::future::BoxFuture;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use sqlx::Executor;
+use std::str::FromStr;
+
+/// Timestamps are stored as Unix microseconds so they sort and compare as integers
+const SCHEMA: &str = "\
+    CREATE TABLE IF NOT EXISTS telemetry (
+        tenant_id TEXT NOT NULL,
+        device_id TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        humidity REAL NOT NULL,
+        battery REAL NOT NULL,
+        received_at INTEGER,
+        seq INTEGER NOT NULL DEFAULT 0,
+        inserted_at INTEGER NOT NULL DEFAULT (unixepoch('subsec') * 1000000),
+        PRIMARY KEY (tenant_id, device_id, ts, seq)
+    ) WITHOUT ROWID";
+
+/// A local SQLite file for edge gateways, with the same conflict policies as Postgres
+#[derive(Debug, Clone)]
+pub struct SqliteSink {
+    pool: SqlitePool,
+    policy: ConflictPolicy,
+}
+
+impl SqliteSink {
+    pub async fn open(path: &str, policy: ConflictPolicy) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(path)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        // SQLite has a single writer; more connections would only wait on its lock
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        pool.execute(SCHEMA).await?;
+        Ok(Self { pool, policy })
+    }
+
+    async fn insert(&self, batch: &[Envelope]) -> Result<u64> {
+        let query = insert_sql(self.policy);
+        let mut rows = 0;
+        let mut tx = self.pool.begin().await?;
+        for t in batch {
+            let result = sqlx::query(query)
+                .bind(tenant_of(t))
+                .bind(&t.telemetry.device_id)
+                .bind(t.telemetry.timestamp.timestamp_micros())
+                .bind(t.telemetry.temperature)
+                .bind(t.telemetry.humidity)
+                .bind(t.telemetry.battery)
+                .bind(t.received_at.map(|r| r.timestamp_micros()))
+                .execute(&mut *tx)
+                .await?;
+            rows += result.rows_affected();
+        }
+        tx.commit().await?;
+        Ok(rows)
+    }
+}
+
+/// Rows are inserted one by one in a transaction, so a reading sees the
+/// ones before it in the same batch
+fn insert_sql(policy: ConflictPolicy) -> &'static str {
+    match policy {
+        ConflictPolicy::Ignore => {
+            "INSERT INTO telemetry (tenant_id, device_id, ts, temperature, humidity, battery, received_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) ON CONFLICT DO NOTHING"
+        }
+        ConflictPolicy::Overwrite => {
+            "INSERT INTO telemetry (tenant_id, device_id, ts, temperature, humidity, battery, received_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+             ON CONFLICT DO UPDATE SET temperature = excluded.temperature, humidity = excluded.humidity, \
+             battery = excluded.battery, received_at = excluded.received_at, \
+             inserted_at = unixepoch('subsec') * 1000000"
+        }
+        ConflictPolicy::KeepLatestByArrival => {
+            "INSERT INTO telemetry (tenant_id, device_id, ts, temperature, humidity, battery, received_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+             ON CONFLICT DO UPDATE SET temperature = excluded.temperature, humidity = excluded.humidity, \
+             battery = excluded.battery, received_at = excluded.received_at, \
+             inserted_at = unixepoch('subsec') * 1000000 \
+             WHERE telemetry.received_at IS NULL OR telemetry.received_at <= excluded.received_at"
+        }
+        ConflictPolicy::KeepBoth => {
+            "INSERT INTO telemetry (tenant_id, device_id, ts, temperature, humidity, battery, received_at, seq) \
+             SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, COALESCE(max(seq) + 1, 0) FROM telemetry \
+             WHERE tenant_id = ?1 AND device_id = ?2 AND ts = ?3"
+        }
+    }
+}
+
+impl TelemetrySink for SqliteSink {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    fn write<'a>(&'a self, batch: &'a [Envelope]) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(self.insert(batch))
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async {
+            self.pool.close().await;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use crate::model::Telemetry;
+
+    async fn stored(sink: &SqliteSink) -> Vec<(String, i64, f64)> {
+        sqlx::query_as("SELECT device_id, seq, temperature FROM telemetry ORDER BY device_id, ts, seq")
+            .fetch_all(&sink.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_conflict_policies() {
+        let now = Utc::now();
+        let reading = |device_id: &str, temperature: f64, received_secs: i64| {
+            Envelope {
+                received_at: Some(now + Duration::seconds(received_secs)),
+                ..Telemetry::new(device_id, now, temperature, 50.0, 90.0).into()
+            }
+        };
+        let batch = [reading("dev-1", 20.0, 2), reading("dev-1", 21.0, 1), reading("dev-2", 30.0, 0)];
+
+        for (policy, rows, expected) in [
+            (ConflictPolicy::Ignore, 2, vec![("dev-1", 0, 20.0), ("dev-2", 0, 30.0)]),
+            (ConflictPolicy::Overwrite, 3, vec![("dev-1", 0, 21.0), ("dev-2", 0, 30.0)]),
+            (ConflictPolicy::KeepLatestByArrival, 2, vec![("dev-1", 0, 20.0), ("dev-2", 0, 30.0)]),
+            (ConflictPolicy::KeepBoth, 3, vec![("dev-1", 0, 20.0), ("dev-1", 1, 21.0), ("dev-2", 0, 30.0)]),
+        ] {
+            let sink = SqliteSink::open("sqlite::memory:", policy).await.unwrap();
+            assert_eq!(sink.write(&batch).await.unwrap(), rows, "{:?}", policy);
+            let expected: Vec<(String, i64, f64)> =
+                expected.into_iter().map(|(d, s, t)| (d.to_string(), s, t)).collect();
+            assert_eq!(stored(&sink).await, expected, "{:?}", policy);
+        }
+    }
+}
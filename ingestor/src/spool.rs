@@ -0,0 +1,151 @@
+use crate::errors::Result;
+use crate::model::{Envelope, Telemetry};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// One spooled reading; keeps the arrival time and tenant the wire format drops
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolRecord {
+    #[serde(flatten)]
+    telemetry: Telemetry,
+    received_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    tenant_id: Option<String>,
+}
+
+/// Append-only NDJSON spool for readings shed under overload.
+///
+/// New records go to `<dir>/<name>.ndjson`. `take` rotates that file to a
+/// `.replay` file so it can be read back while spilling continues.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    name: String,
+    file: Mutex<Option<File>>,
+}
+
+impl Spool {
+    pub fn open(dir: impl AsRef<Path>, name: &str) -> Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            name: name.to_string(),
+            file: Mutex::new(None),
+        })
+    }
+
+    fn active_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.ndjson", self.name))
+    }
+
+    pub fn append(&self, envelope: &Envelope) -> Result<()> {
+        let mut line = serde_json::to_vec(&SpoolRecord {
+            telemetry: envelope.telemetry.clone(),
+            received_at: envelope.received_at,
+            tenant_id: envelope.tenant_id.clone(),
+        })?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.active_path())?,
+            );
+        }
+        file.as_mut().unwrap().write_all(&line)?;
+        Ok(())
+    }
+
+    /// Rotates the active file and returns every file waiting to be replayed, oldest first
+    pub fn take(&self) -> Result<Vec<PathBuf>> {
+        {
+            let mut file = self.file.lock().unwrap();
+            let active = self.active_path();
+            if active.exists() {
+                *file = None;
+                let rotated = self.dir.join(format!(
+                    "{}.{}.replay",
+                    self.name,
+                    Utc::now().timestamp_micros()
+                ));
+                fs::rename(&active, rotated)?;
+            }
+        }
+
+        let prefix = format!("{}.", self.name);
+        let mut pending: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".replay"))
+            })
+            .collect();
+        pending.sort();
+        Ok(pending)
+    }
+
+    /// Reads a file returned by `take`; malformed lines are skipped
+    pub fn read(path: &Path) -> Result<Vec<Envelope>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| match serde_json::from_str::<SpoolRecord>(line) {
+                Ok(record) => Some(Envelope {
+                    received_at: record.received_at,
+                    tenant_id: record.tenant_id,
+                    ..record.telemetry.into()
+                }),
+                Err(e) => {
+                    warn!("Skipping malformed spool record in {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_take_read() {
+        let dir = std::env::temp_dir().join(format!("spool-test-{}", uuid::Uuid::new_v4()));
+        let spool = Spool::open(&dir, "routine").unwrap();
+
+        let envelope = Envelope {
+            received_at: Some(Utc::now()),
+            tenant_id: Some("acme".to_string()),
+            ..Telemetry::new("dev-1", Utc::now(), 25.0, 60.0, 80.0).into()
+        };
+        spool.append(&envelope).unwrap();
+        spool.append(&envelope).unwrap();
+
+        let files = spool.take().unwrap();
+        assert_eq!(files.len(), 1);
+
+        // Spilling continues into a fresh file after rotation
+        spool.append(&envelope).unwrap();
+
+        let records = Spool::read(&files[0]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].telemetry.device_id, "dev-1");
+        assert_eq!(records[0].received_at, envelope.received_at);
+        assert_eq!(records[0].tenant_id.as_deref(), Some("acme"));
+
+        fs::remove_file(&files[0]).unwrap();
+        assert_eq!(spool.take().unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use crate::metrics::{STREAM_LAGGED_TOTAL, STREAM_SUBSCRIBERS};
+use crate::model::Envelope;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Fans validated readings out to live stream subscribers.
+///
+/// Readings are filtered before fan-out, and each subscriber has its own
+/// bounded queue of `capacity` matching readings. When a subscriber's queue is
+/// full its readings are dropped and it is told how many it missed, so a slow
+/// client only lags itself and never holds up ingestion.
+#[derive(Debug)]
+pub struct Broadcaster {
+    capacity: usize,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    filter: StreamFilter,
+    tx: mpsc::Sender<Arc<Envelope>>,
+    skipped: Arc<AtomicU64>,
+}
+
+impl Broadcaster {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// True when at least one client is connected, so readings need copying
+    pub fn has_subscribers(&self) -> bool {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|subscriber| !subscriber.tx.is_closed())
+    }
+
+    pub fn publish(&self, envelope: Envelope) {
+        let envelope = Arc::new(envelope);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| !subscriber.tx.is_closed());
+        for subscriber in subscribers.iter().filter(|s| s.filter.matches(&envelope)) {
+            if let Err(TrySendError::Full(_)) = subscriber.tx.try_send(envelope.clone()) {
+                subscriber.skipped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn subscribe(&self, filter: StreamFilter) -> Subscription {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let skipped = Arc::new(AtomicU64::new(0));
+        self.subscribers.lock().unwrap().push(Subscriber {
+            filter,
+            tx,
+            skipped: skipped.clone(),
+        });
+        STREAM_SUBSCRIBERS.inc();
+        Subscription { rx, skipped }
+    }
+}
+
+/// Which readings a subscriber wants; empty fields match everything
+#[derive(Debug, Default, Clone)]
+pub struct StreamFilter {
+    pub tenant: Option<String>,
+    pub device_ids: Vec<String>,
+    pub device_prefix: Option<String>,
+    pub priority: Option<String>,
+}
+
+impl StreamFilter {
+    pub fn matches(&self, envelope: &Envelope) -> bool {
+        let device_id = &envelope.telemetry.device_id;
+        self.tenant
+            .as_ref()
+            .is_none_or(|tenant| envelope.tenant_id.as_ref() == Some(tenant))
+            && (self.device_ids.is_empty() || self.device_ids.contains(device_id))
+            && self
+                .device_prefix
+                .as_ref()
+                .is_none_or(|prefix| device_id.starts_with(prefix.as_str()))
+            && self
+                .priority
+                .as_ref()
+                .is_none_or(|priority| envelope.priority.as_ref() == Some(priority))
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamItem {
+    Reading(Arc<Envelope>),
+    /// The subscriber fell behind and this many readings were skipped
+    Lagged(u64),
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    rx: mpsc::Receiver<Arc<Envelope>>,
+    skipped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Next matching reading or lag notice; `None` once ingestion stops
+    pub async fn next(&mut self) -> Option<StreamItem> {
+        let skipped = self.skipped.swap(0, Ordering::Relaxed);
+        if skipped > 0 {
+            STREAM_LAGGED_TOTAL.inc_by(skipped as f64);
+            return Some(StreamItem::Lagged(skipped));
+        }
+        self.rx.recv().await.map(StreamItem::Reading)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        STREAM_SUBSCRIBERS.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Telemetry;
+    use chrono::Utc;
+
+    fn reading(device_id: &str, priority: Option<&str>) -> Envelope {
+        Envelope {
+            priority: priority.map(str::to_string),
+            tenant_id: Some("acme".to_string()),
+            ..Telemetry::new(device_id, Utc::now(), 25.0, 60.0, 80.0).into()
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = StreamFilter {
+            tenant: Some("acme".to_string()),
+            device_prefix: Some("pump-".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&reading("pump-1", None)));
+        assert!(!filter.matches(&reading("valve-1", None)));
+
+        let filter = StreamFilter {
+            tenant: Some("globex".to_string()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&reading("pump-1", None)));
+
+        let filter = StreamFilter {
+            device_ids: vec!["a".to_string(), "b".to_string()],
+            priority: Some("critical".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&reading("b", Some("critical"))));
+        assert!(!filter.matches(&reading("b", None)));
+        assert!(!filter.matches(&reading("c", Some("critical"))));
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_lags_without_blocking() {
+        let broadcaster = Broadcaster::new(4);
+        assert!(!broadcaster.has_subscribers());
+
+        let mut subscription = broadcaster.subscribe(StreamFilter {
+            device_ids: vec!["dev-1".to_string()],
+            ..Default::default()
+        });
+        assert!(broadcaster.has_subscribers());
+
+        // Publishing never waits for the subscriber, and only the 5 matching
+        // readings count against its buffer
+        for i in 0..10 {
+            let device_id = if i % 2 == 0 { "dev-1" } else { "dev-2" };
+            broadcaster.publish(reading(device_id, None));
+        }
+
+        assert!(matches!(subscription.next().await, Some(StreamItem::Lagged(1))));
+        for _ in 0..4 {
+            match subscription.next().await {
+                Some(StreamItem::Reading(t)) => assert_eq!(t.telemetry.device_id, "dev-1"),
+                other => panic!("expected a reading, got {:?}", other),
+            }
+        }
+
+        drop(broadcaster);
+        assert!(subscription.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lag_is_per_subscriber() {
+        let broadcaster = Broadcaster::new(4);
+        let mut quiet = broadcaster.subscribe(StreamFilter {
+            device_ids: vec!["quiet".to_string()],
+            ..Default::default()
+        });
+        let mut busy = broadcaster.subscribe(StreamFilter::default());
+
+        // A flooding device fills the unfiltered subscriber's buffer only
+        broadcaster.publish(reading("quiet", None));
+        for _ in 0..100 {
+            broadcaster.publish(reading("flood", None));
+        }
+        broadcaster.publish(reading("quiet", None));
+
+        for _ in 0..2 {
+            match quiet.next().await {
+                Some(StreamItem::Reading(t)) => assert_eq!(t.telemetry.device_id, "quiet"),
+                other => panic!("expected a reading, got {:?}", other),
+            }
+        }
+        assert!(matches!(busy.next().await, Some(StreamItem::Lagged(98))));
+
+        // Dropped subscribers are pruned on the next publish
+        drop(busy);
+        drop(quiet);
+        assert!(!broadcaster.has_subscribers());
+        broadcaster.publish(reading("quiet", None));
+        assert!(broadcaster.subscribers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+use crate::errors::{Error, Result};
+use crate::metrics::{TENANT_QUOTA_EXCEEDED_TOTAL, TENANT_RETENTION_DELETED_TOTAL};
+use crate::ratelimit::{Limit, TokenBucket};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+/// Tenant that owns every row when no tenants are configured
+pub const DEFAULT_TENANT: &str = "default";
+
+/// How often expired rows are purged for tenants with a retention setting
+const RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// One customer sharing the deployment
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct TenantConfig {
+    pub id: String,
+    /// Topic prefixes whose messages belong to this tenant
+    #[serde(default)]
+    pub topic_prefixes: Vec<String>,
+    /// Device registry: devices belonging to this tenant whatever topic they publish on
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Broker usernames of this tenant's publishers, read from the topic level
+    /// named by `username_level`
+    #[serde(default)]
+    pub usernames: Vec<String>,
+    /// Messages per second shared by all of the tenant's devices; unset is unlimited
+    #[serde(default)]
+    pub quota: Option<Limit>,
+    /// Rows older than this are deleted; unset keeps them forever
+    #[serde(default)]
+    pub retention_days: Option<u32>,
+}
+
+/// Tenant layout, loaded from the JSON file named by `TENANTS_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantsConfig {
+    pub tenants: Vec<TenantConfig>,
+    /// Topic level (0-based) holding the publisher's broker username. MQTT does
+    /// not pass credentials on to subscribers, so this relies on the broker's
+    /// ACL confining each username to its own level, e.g. Mosquitto's
+    /// `pattern write telemetry/%u/#`
+    #[serde(default)]
+    pub username_level: Option<usize>,
+    /// Tenant for messages matching no device or topic prefix
+    #[serde(default = "default_tenant")]
+    pub default_tenant: String,
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+impl Default for TenantsConfig {
+    fn default() -> Self {
+        Self {
+            tenants: Vec::new(),
+            username_level: None,
+            default_tenant: default_tenant(),
+        }
+    }
+}
+
+impl TenantsConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&contents)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    fn check(&self) -> Result<()> {
+        let mut devices: HashMap<&str, &str> = HashMap::new();
+        let mut usernames: HashMap<&str, &str> = HashMap::new();
+        for (i, tenant) in self.tenants.iter().enumerate() {
+            if tenant.id.is_empty() || self.tenants[..i].iter().any(|t| t.id == tenant.id) {
+                return Err(Error::Validation(format!(
+                    "tenant id {:?} is empty or duplicated",
+                    tenant.id
+                )));
+            }
+            for device_id in &tenant.devices {
+                if let Some(other) = devices.insert(device_id, &tenant.id) {
+                    return Err(Error::Validation(format!(
+                        "device {} is registered to both {} and {}",
+                        device_id, other, tenant.id
+                    )));
+                }
+            }
+            for username in &tenant.usernames {
+                if let Some(other) = usernames.insert(username, &tenant.id) {
+                    return Err(Error::Validation(format!(
+                        "username {} is registered to both {} and {}",
+                        username, other, tenant.id
+                    )));
+                }
+            }
+        }
+        if self.username_level.is_none() && !usernames.is_empty() {
+            return Err(Error::Validation(
+                "tenant usernames need username_level".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the tenant of each message and enforces tenant quotas
+#[derive(Debug)]
+pub struct TenantRegistry {
+    config: TenantsConfig,
+    devices: HashMap<String, usize>,
+    usernames: HashMap<String, usize>,
+    quotas: Mutex<HashMap<usize, TokenBucket>>,
+}
+
+impl Default for TenantRegistry {
+    fn default() -> Self {
+        Self::new(TenantsConfig::default())
+    }
+}
+
+impl TenantRegistry {
+    pub fn new(config: TenantsConfig) -> Self {
+        let devices = config
+            .tenants
+            .iter()
+            .enumerate()
+            .flat_map(|(i, t)| t.devices.iter().map(move |d| (d.clone(), i)))
+            .collect();
+        let usernames = config
+            .tenants
+            .iter()
+            .enumerate()
+            .flat_map(|(i, t)| t.usernames.iter().map(move |u| (u.clone(), i)))
+            .collect();
+        let now = Instant::now();
+        let quotas = config
+            .tenants
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| t.quota.map(|q| (i, TokenBucket::new(q, now))))
+            .collect();
+        Self {
+            config,
+            devices,
+            usernames,
+            quotas: Mutex::new(quotas),
+        }
+    }
+
+    pub fn tenants(&self) -> &[TenantConfig] {
+        &self.config.tenants
+    }
+
+    /// True for configured tenants and the default tenant
+    pub fn contains(&self, tenant_id: &str) -> bool {
+        tenant_id == self.config.default_tenant || self.index(tenant_id).is_some()
+    }
+
+    fn index(&self, tenant_id: &str) -> Option<usize> {
+        self.config.tenants.iter().position(|t| t.id == tenant_id)
+    }
+
+    /// Device registry first, then the publisher's username, then the longest
+    /// matching topic prefix, then the default tenant
+    pub fn resolve(&self, topic: &str, device_id: &str) -> &str {
+        if let Some(&i) = self.devices.get(device_id) {
+            return &self.config.tenants[i].id;
+        }
+        let username = self.config.username_level.and_then(|level| topic.split('/').nth(level));
+        if let Some(&i) = username.and_then(|u| self.usernames.get(u)) {
+            return &self.config.tenants[i].id;
+        }
+        self.config
+            .tenants
+            .iter()
+            .flat_map(|t| t.topic_prefixes.iter().map(move |p| (p, t)))
+            .filter(|(prefix, _)| topic.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, t)| t.id.as_str())
+            .unwrap_or(&self.config.default_tenant)
+    }
+
+    /// Charges one message to the tenant's quota
+    pub fn check_quota(&self, tenant_id: &str) -> Result<()> {
+        self.check_quota_at(tenant_id, Instant::now())
+    }
+
+    fn check_quota_at(&self, tenant_id: &str, now: Instant) -> Result<()> {
+        let Some(i) = self.index(tenant_id) else {
+            return Ok(());
+        };
+        let mut quotas = self.quotas.lock().unwrap();
+        let allowed = quotas.get_mut(&i).is_none_or(|bucket| bucket.try_take(now));
+        if allowed {
+            return Ok(());
+        }
+        TENANT_QUOTA_EXCEEDED_TOTAL
+            .with_label_values(&[tenant_id])
+            .inc();
+        Err(Error::RateLimited(format!(
+            "Tenant {} is over its quota",
+            tenant_id
+        )))
+    }
+}
+
+/// Periodically deletes rows past each tenant's retention, if any tenant sets one
+pub fn spawn_retention(pool: PgPool, registry: Arc<TenantRegistry>) {
+    if registry.tenants().iter().all(|t| t.retention_days.is_none()) {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for tenant in registry.tenants() {
+                let Some(days) = tenant.retention_days else {
+                    continue;
+                };
+                match purge_expired(&pool, &tenant.id, days).await {
+                    Ok(0) => {}
+                    Ok(deleted) => info!(
+                        "Retention removed {} rows older than {} days for tenant {}",
+                        deleted, days, tenant.id
+                    ),
+                    Err(e) => error!("Retention for tenant {} failed: {}", tenant.id, e),
+                }
+            }
+        }
+    });
+}
+
+async fn purge_expired(pool: &PgPool, tenant_id: &str, days: u32) -> Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM telemetry WHERE tenant_id = $1 AND ts < now() - make_interval(days => $2)",
+    )
+    .bind(tenant_id)
+    .bind(days as i32)
+    .execute(pool)
+    .await?;
+    TENANT_RETENTION_DELETED_TOTAL
+        .with_label_values(&[tenant_id])
+        .inc_by(result.rows_affected() as f64);
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> TenantRegistry {
+        TenantRegistry::new(
+            serde_json::from_str(include_str!("../../deploy/tenants.example.json")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_resolve_order() {
+        let registry = registry();
+
+        // Registered device wins over the topic it publishes on
+        assert_eq!(registry.resolve("telemetry/globex/telemetry/pump-7", "acme-pump-7"), "acme");
+        assert_eq!(registry.resolve("telemetry/globex/telemetry/dev-1", "dev-1"), "globex");
+        assert_eq!(registry.resolve("telemetry/acme-gateway/dev-1", "dev-1"), "acme");
+        assert_eq!(registry.resolve("telemetry/dev-1", "dev-1"), "default");
+
+        assert!(registry.contains("acme"));
+        assert!(registry.contains("default"));
+        assert!(!registry.contains("initech"));
+    }
+
+    #[test]
+    fn test_resolve_username() {
+        let config: TenantsConfig = serde_json::from_str(
+            r#"{ "username_level": 1, "tenants": [
+                { "id": "acme", "usernames": ["acme-gw"], "devices": ["pump-7"] },
+                { "id": "globex", "topic_prefixes": ["telemetry/globex/"] }
+            ] }"#,
+        )
+        .unwrap();
+        config.check().unwrap();
+        let registry = TenantRegistry::new(config);
+
+        assert_eq!(registry.resolve("telemetry/acme-gw/dev-1", "dev-1"), "acme");
+        // Unknown usernames fall through to the topic prefixes; the device
+        // registry wins over the username
+        assert_eq!(registry.resolve("telemetry/globex/dev-1", "dev-1"), "globex");
+        assert_eq!(registry.resolve("telemetry/globex/pump-7", "pump-7"), "acme");
+        assert_eq!(registry.resolve("telemetry", "dev-1"), "default");
+    }
+
+    #[test]
+    fn test_quota() {
+        let registry = registry();
+        let now = Instant::now();
+
+        // acme: burst of 2000
+        for _ in 0..2000 {
+            assert!(registry.check_quota_at("acme", now).is_ok());
+        }
+        assert!(matches!(
+            registry.check_quota_at("acme", now),
+            Err(Error::RateLimited(_))
+        ));
+
+        // No quota configured
+        assert!(registry.check_quota_at("default", now).is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_device_rejected() {
+        let config: TenantsConfig = serde_json::from_str(
+            r#"{ "tenants": [
+                { "id": "a", "devices": ["dev-1"] },
+                { "id": "b", "devices": ["dev-1"] }
+            ] }"#,
+        )
+        .unwrap();
+        assert!(config.check().is_err());
+
+        let config: TenantsConfig =
+            serde_json::from_str(r#"{ "tenants": [{ "id": "a", "usernames": ["gw"] }] }"#).unwrap();
+        assert!(config.check().is_err());
+    }
+}
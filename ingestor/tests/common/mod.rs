@@ -0,0 +1,133 @@
+// Each test crate uses a different part of this module
+#![allow(dead_code)]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use ingestor::auth::Authenticator;
+use ingestor::batching::{run_batcher, BatcherConfig};
+use ingestor::db::{ConflictPolicy, WriteStrategy};
+use ingestor::harness::{TestBroker, TestDatabase};
+use ingestor::lanes::{self, Backpressure, LanesConfig};
+use ingestor::mqtt::MqttSource;
+use ingestor::pipeline::{Pipeline, RangeValidator};
+use ingestor::sink::PostgresSink;
+use ingestor::stream::{Broadcaster, StreamFilter};
+use ingestor::tenant::TenantRegistry;
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// The ingestor's own tasks between a test broker and a test database, wired
+/// the way the binary wires them
+pub struct Ingestor {
+    pub broker: TestBroker,
+    pub pool: PgPool,
+    pub stream: Arc<Broadcaster>,
+    pub router: Router,
+    _database: TestDatabase,
+}
+
+impl Ingestor {
+    pub async fn start() -> Self {
+        let database = TestDatabase::start().await;
+        let pool = database.pool().await;
+        let broker = TestBroker::start(env!("CARGO_BIN_EXE_test-broker")).await;
+
+        let spool_dir = std::env::temp_dir()
+            .join(format!("ingestor-spool-{}", uuid::Uuid::new_v4()))
+            .display()
+            .to_string();
+        let (tx, rx) =
+            lanes::channels(&LanesConfig::single(10_000, Backpressure::Block, None, spool_dir)).unwrap();
+        let tenants = Arc::new(TenantRegistry::default());
+        let stream = Arc::new(Broadcaster::new(16));
+        let pipeline = Pipeline::builder()
+            .source(MqttSource::new("127.0.0.1", broker.port, "ingestor-test"))
+            .validator(RangeValidator)
+            .processor(tenants.clone())
+            .sink(tx)
+            .sink(stream.clone())
+            .build()
+            .unwrap();
+        tokio::spawn(pipeline.run());
+        let config = BatcherConfig {
+            workers: 2,
+            max_batch: 500,
+            max_wait_ms: 20,
+            dedup_window_secs: 60,
+            dedup_max_entries: 100_000,
+            adaptive: None,
+        };
+        let sink = PostgresSink::new(pool.clone(), WriteStrategy::Unnest, ConflictPolicy::Ignore);
+        tokio::spawn(run_batcher(rx, vec![Arc::new(sink)], config));
+        let router = ingestor::rest::create_router(
+            pool.clone(),
+            None,
+            Arc::new(Authenticator::disabled(pool.clone())),
+            tenants,
+            stream.clone(),
+        );
+        Self {
+            broker,
+            pool,
+            stream,
+            router,
+            _database: database,
+        }
+    }
+
+    pub fn client(&self, id: &str) -> AsyncClient {
+        let mut options = MqttOptions::new(id, "127.0.0.1", self.broker.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut eventloop) = AsyncClient::new(options, 20_000);
+        tokio::spawn(async move { while eventloop.poll().await.is_ok() {} });
+        client
+    }
+
+    /// Repeats `payload` on `topic` until the pipeline has seen it. The broker
+    /// keeps nothing for filters subscribed later, so this waits for the
+    /// ingestor's subscription; the batcher drops the repeats as redeliveries.
+    pub async fn wait_subscribed(&self, client: &AsyncClient, topic: &str, payload: &str) {
+        let mut seen = self.stream.subscribe(StreamFilter::default());
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                client.publish(topic, QoS::AtLeastOnce, false, payload).await.unwrap();
+                if let Ok(Some(_)) = tokio::time::timeout(Duration::from_millis(100), seen.next()).await {
+                    return;
+                }
+            }
+        })
+        .await
+        .expect("ingestor not subscribed after 10s");
+    }
+
+    /// Rows stored, polled until `expected` or `timeout`
+    pub async fn wait_stored(&self, expected: i64, timeout: Duration) -> i64 {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let (stored,): (i64,) = sqlx::query_as("SELECT count(*) FROM telemetry")
+                .fetch_one(&self.pool)
+                .await
+                .unwrap();
+            if stored >= expected || tokio::time::Instant::now() >= deadline {
+                return stored;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    pub async fn get(&self, uri: &str) -> serde_json::Value {
+        let response = self
+            .router
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+}
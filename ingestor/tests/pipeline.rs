@@ -0,0 +1,43 @@
+mod common;
+
+use common::Ingestor;
+use rumqttc::QoS;
+use std::time::Duration;
+
+/// Publishes over MQTT and reads the readings back through the REST API,
+/// with the ingestor's own tasks in between
+#[tokio::test]
+async fn test_mqtt_to_rest_pipeline() {
+    let ingestor = Ingestor::start().await;
+    let publisher = ingestor.client("pipeline-sim");
+
+    let first = r#"{"device_id":"pipeline-0","timestamp":"2025-10-05T12:00:00.000Z","temperature":20,"humidity":50,"battery":90}"#;
+    ingestor.wait_subscribed(&publisher, "telemetry/pipeline-0", first).await;
+
+    for i in 0..100 {
+        let payload = serde_json::json!({
+            "device_id": format!("pipeline-{}", i % 4),
+            "timestamp": format!("2025-10-05T12:00:{:02}.{:03}Z", i / 10, i),
+            "temperature": 20.0 + i as f64 / 10.0,
+            "humidity": 50.0,
+            "battery": 90.0,
+        });
+        let topic = format!("telemetry/pipeline-{}", i % 4);
+        publisher.publish(topic, QoS::AtLeastOnce, false, payload.to_string()).await.unwrap();
+    }
+    // Invalid, and a QoS 1 redelivery of a reading already sent
+    publisher.publish("telemetry/pipeline-0", QoS::AtLeastOnce, false, "{").await.unwrap();
+    publisher.publish("telemetry/pipeline-0", QoS::AtLeastOnce, false, first).await.unwrap();
+
+    assert_eq!(ingestor.wait_stored(100, Duration::from_secs(10)).await, 100);
+    let body = ingestor.get("/api/v1/telemetry?device_prefix=pipeline-&limit=1000").await;
+    assert_eq!(body["total"], 100, "{}", body);
+    assert_eq!(body["data"][0]["device_id"], "pipeline-3");
+    assert_eq!(body["data"][0]["temperature"], 29.9);
+
+    let stored: (i64,) = sqlx::query_as("SELECT count(DISTINCT device_id) FROM telemetry")
+        .fetch_one(&ingestor.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.0, 4);
+}
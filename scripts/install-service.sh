@@ -29,6 +29,7 @@ fi
 mkdir -p "$INSTALL_DIR/bin"
 mkdir -p "$INSTALL_DIR/migrations"
 mkdir -p /var/log/iot-ingestor
+mkdir -p /var/lib/iot-ingestor/spool
 
 cp "$BINARY_PATH" "$INSTALL_DIR/bin/ingestor"
 cp -r ingestor/migrations/* "$INSTALL_DIR/migrations/"
@@ -38,6 +39,7 @@ chmod +x scripts/start-dependencies.sh
 
 chown -R "$SERVICE_USER:$SERVICE_GROUP" "$INSTALL_DIR"
 chown -R "$SERVICE_USER:$SERVICE_GROUP" /var/log/iot-ingestor
+chown -R "$SERVICE_USER:$SERVICE_GROUP" /var/lib/iot-ingestor
 
 cp ingestor/systemd/iot-ingestor.service /etc/systemd/system/
 systemctl daemon-reload
@@ -0,0 +1,318 @@
+use crate::scenario::{Battery, Dropout, Fleet, Model, ReconnectStorm, Sensor, SimTime};
+use chrono::{DateTime, Timelike, Utc};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use telemetry_model::Telemetry;
+
+/// One simulated device and the state its behavior models carry between readings
+#[derive(Debug)]
+pub struct Device {
+    pub id: String,
+    pub fleet: String,
+    temperature: SensorState,
+    humidity: SensorState,
+    battery: BatteryState,
+    dropout: Option<Dropout>,
+    storm: Option<ReconnectStorm>,
+    offline_until: f64,
+    backlog: VecDeque<Telemetry>,
+    last_sample: Option<f64>,
+}
+
+#[derive(Debug)]
+struct SensorState {
+    config: Sensor,
+    offset: f64,
+    walk: f64,
+    last: Option<f64>,
+    stuck_until: f64,
+}
+
+#[derive(Debug)]
+struct BatteryState {
+    config: Battery,
+    level: f64,
+    charging: bool,
+}
+
+impl Device {
+    pub fn new(id: String, fleet: &Fleet, rng: &mut impl Rng) -> Self {
+        let [low, high] = fleet.battery.start;
+        Self {
+            id,
+            fleet: fleet.name.clone(),
+            temperature: SensorState::new(&fleet.temperature, rng),
+            humidity: SensorState::new(&fleet.humidity, rng),
+            battery: BatteryState {
+                config: fleet.battery.clone(),
+                level: range(rng, low, high).clamp(0.0, 100.0),
+                charging: false,
+            },
+            dropout: fleet.dropout.clone(),
+            storm: fleet.reconnect_storm.clone(),
+            offline_until: 0.0,
+            backlog: VecDeque::new(),
+            last_sample: None,
+        }
+    }
+
+    /// Readings the device publishes on this turn: none while it is offline,
+    /// several when it reconnects after a storm with buffered readings
+    pub fn sample(&mut self, now: SimTime, timestamp: DateTime<Utc>, rng: &mut impl Rng) -> Vec<Telemetry> {
+        let hours = self
+            .last_sample
+            .map_or(0.0, |last| (now.elapsed_secs - last).max(0.0) / 3600.0);
+        self.last_sample = Some(now.elapsed_secs);
+        self.battery.advance(hours);
+
+        if now.elapsed_secs < self.offline_until {
+            return Vec::new();
+        }
+        if let Some(dropout) = &self.dropout {
+            if rng.gen_bool(dropout.probability.clamp(0.0, 1.0)) {
+                self.offline_until = now.elapsed_secs + range(rng, dropout.min_secs, dropout.max_secs);
+                return Vec::new();
+            }
+        }
+
+        let reading = Telemetry::new(
+            self.id.clone(),
+            timestamp,
+            self.temperature.sample(now, rng),
+            self.humidity.sample(now, rng),
+            self.battery.level,
+        );
+
+        if let Some(storm) = &self.storm {
+            if now.elapsed_secs % storm.every_secs < storm.outage_secs {
+                if storm.backlog > 0 {
+                    if self.backlog.len() == storm.backlog {
+                        self.backlog.pop_front();
+                    }
+                    self.backlog.push_back(reading);
+                }
+                return Vec::new();
+            }
+        }
+
+        let mut readings: Vec<Telemetry> = self.backlog.drain(..).collect();
+        readings.push(reading);
+        readings
+    }
+}
+
+impl SensorState {
+    fn new(config: &Sensor, rng: &mut impl Rng) -> Self {
+        let offset = range(rng, -config.jitter, config.jitter);
+        let walk = match config.model {
+            Model::RandomWalk { start, min, max, .. } => (start + offset).clamp(min, max),
+            _ => 0.0,
+        };
+        Self {
+            config: config.clone(),
+            offset,
+            walk,
+            last: None,
+            stuck_until: 0.0,
+        }
+    }
+
+    fn sample(&mut self, now: SimTime, rng: &mut impl Rng) -> f64 {
+        let value = match self.last {
+            Some(last) if now.elapsed_secs < self.stuck_until => last,
+            _ => {
+                let value = self.model_value(now, rng)
+                    + self.config.drift_per_hour * now.elapsed_secs / 3600.0;
+                match &self.config.stuck {
+                    Some(stuck) if rng.gen_bool(stuck.probability.clamp(0.0, 1.0)) => {
+                        self.stuck_until = now.elapsed_secs + stuck.duration_secs;
+                    }
+                    _ => {}
+                }
+                value
+            }
+        };
+        self.last = Some(value);
+
+        match &self.config.outliers {
+            Some(outliers) if rng.gen_bool(outliers.probability.clamp(0.0, 1.0)) => {
+                range(rng, outliers.min, outliers.max)
+            }
+            _ => value,
+        }
+    }
+
+    fn model_value(&mut self, now: SimTime, rng: &mut impl Rng) -> f64 {
+        match self.config.model {
+            Model::Constant { value } => value + self.offset,
+            Model::Uniform { min, max } => range(rng, min, max) + self.offset,
+            Model::Diurnal {
+                mean,
+                amplitude,
+                peak_hour,
+                noise,
+            } => {
+                let hour = now.at.num_seconds_from_midnight() as f64 / 3600.0;
+                mean + self.offset
+                    + amplitude * (2.0 * PI * (hour - peak_hour) / 24.0).cos()
+                    + range(rng, -noise, noise)
+            }
+            Model::RandomWalk { step, min, max, .. } => {
+                self.walk = (self.walk + range(rng, -step, step)).clamp(min, max);
+                self.walk
+            }
+        }
+    }
+}
+
+impl BatteryState {
+    fn advance(&mut self, hours: f64) {
+        if self.charging {
+            self.level += self.config.recharge_per_hour * hours;
+            if self.level >= 100.0 {
+                self.level = 100.0;
+                self.charging = false;
+            }
+        } else {
+            self.level = (self.level - self.config.drain_per_hour * hours).max(0.0);
+            if self.config.recharge_below.is_some_and(|below| self.level < below) {
+                self.charging = true;
+            }
+        }
+    }
+}
+
+/// Uniform value from `low..=high`, tolerating an empty or reversed range
+fn range(rng: &mut impl Rng, low: f64, high: f64) -> f64 {
+    if high > low {
+        rng.gen_range(low..=high)
+    } else {
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use chrono::Duration;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn at(elapsed_secs: f64) -> SimTime {
+        let origin: DateTime<Utc> = "2025-10-05T00:00:00Z".parse().unwrap();
+        SimTime {
+            elapsed_secs,
+            at: origin + Duration::milliseconds((elapsed_secs * 1000.0) as i64),
+        }
+    }
+
+    fn fleet(json: &str) -> Fleet {
+        let scenario: Scenario = serde_json::from_str(&format!(r#"{{ "fleets": [{}] }}"#, json)).unwrap();
+        scenario.fleets[0].clone()
+    }
+
+    #[test]
+    fn test_smooth_readings() {
+        let fleet = fleet(
+            r#"{ "name": "a", "count": 1,
+                 "temperature": { "model": "diurnal", "mean": 20, "amplitude": 5, "peak_hour": 12 },
+                 "humidity": { "model": "random_walk", "start": 50, "step": 1, "min": 40, "max": 60 } }"#,
+        );
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut device = Device::new("a-0".to_string(), &fleet, &mut rng);
+
+        let mut previous: Option<Telemetry> = None;
+        for minute in 0..24 * 60 {
+            let now = at(minute as f64 * 60.0);
+            let reading = device.sample(now, now.at, &mut rng).pop().unwrap();
+            if let Some(previous) = previous {
+                assert!((reading.humidity - previous.humidity).abs() <= 1.0);
+                assert!((reading.temperature - previous.temperature).abs() < 0.1);
+            }
+            assert!((40.0..=60.0).contains(&reading.humidity));
+            if minute == 0 {
+                assert!((reading.temperature - 15.0).abs() < 0.01, "{}", reading.temperature);
+            } else if minute == 12 * 60 {
+                assert!((reading.temperature - 25.0).abs() < 0.01, "{}", reading.temperature);
+            }
+            previous = Some(reading);
+        }
+    }
+
+    #[test]
+    fn test_battery_drains_and_recharges() {
+        let fleet = fleet(
+            r#"{ "name": "a", "count": 1,
+                 "battery": { "start": [20, 20], "drain_per_hour": 1, "recharge_below": 10, "recharge_per_hour": 10 } }"#,
+        );
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut device = Device::new("a-0".to_string(), &fleet, &mut rng);
+
+        let mut battery = |hours: f64| {
+            let now = at(hours * 3600.0);
+            device.sample(now, now.at, &mut rng).pop().unwrap().battery
+        };
+        assert_eq!(battery(0.0), 20.0);
+        assert!((battery(5.0) - 15.0).abs() < 1e-9);
+        // Drops below 10 at hour 10.5, then charges at 10%/h
+        assert!((battery(10.5) - 9.5).abs() < 1e-9);
+        assert!((battery(11.5) - 19.5).abs() < 1e-9);
+        assert_eq!(battery(30.0), 100.0);
+        assert!((battery(31.0) - 99.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stuck_sensor_repeats_value() {
+        let fleet = fleet(
+            r#"{ "name": "a", "count": 1,
+                 "temperature": { "model": "uniform", "min": 0, "max": 100, "stuck": { "probability": 1, "duration_secs": 60 } } }"#,
+        );
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut device = Device::new("a-0".to_string(), &fleet, &mut rng);
+
+        let mut temperature = |secs: f64| device.sample(at(secs), at(secs).at, &mut rng).pop().unwrap().temperature;
+        let stuck = temperature(0.0);
+        assert_eq!(temperature(30.0), stuck);
+        assert_eq!(temperature(59.0), stuck);
+        assert_ne!(temperature(60.0), stuck);
+    }
+
+    #[test]
+    fn test_reconnect_storm_flushes_backlog() {
+        let fleet = fleet(
+            r#"{ "name": "a", "count": 1,
+                 "reconnect_storm": { "every_secs": 100, "outage_secs": 50, "backlog": 3 } }"#,
+        );
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut device = Device::new("a-0".to_string(), &fleet, &mut rng);
+
+        for secs in [0.0, 10.0, 20.0, 30.0, 40.0] {
+            assert!(device.sample(at(secs), at(secs).at, &mut rng).is_empty());
+        }
+        let burst = device.sample(at(50.0), at(50.0).at, &mut rng);
+        let times: Vec<_> = burst.iter().map(|t| t.timestamp).collect();
+        // Oldest buffered readings were dropped, the rest arrive with their own timestamps
+        assert_eq!(times, vec![at(20.0).at, at(30.0).at, at(40.0).at, at(50.0).at]);
+        assert_eq!(device.sample(at(60.0), at(60.0).at, &mut rng).len(), 1);
+    }
+
+    #[test]
+    fn test_seeded_runs_repeat() {
+        let scenario: Scenario =
+            serde_json::from_str(include_str!("../../deploy/scenario.example.json")).unwrap();
+        let run = || {
+            let mut rng = StdRng::seed_from_u64(42);
+            let mut devices = scenario.devices(&mut rng);
+            (0..100)
+                .flat_map(|i| {
+                    let now = at(i as f64 * 30.0);
+                    let device = i % devices.len();
+                    devices[device].sample(now, now.at, &mut rng)
+                })
+                .map(|t| serde_json::to_string(&t).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,442 @@
+use chrono::Duration as ChronoDuration;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use telemetry_model::Telemetry;
+
+const NUMERIC_FIELDS: [&str; 3] = ["temperature", "humidity", "battery"];
+const FIELDS: [&str; 5] = ["device_id", "timestamp", "temperature", "humidity", "battery"];
+
+/// A defect injected into a published message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    MalformedJson,
+    MissingField,
+    WrongType,
+    NonFinite,
+    EmptyDeviceId,
+    FutureTimestamp,
+    PastTimestamp,
+    Duplicate,
+    Oversized,
+    OutOfOrder,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 10] = [
+        Fault::MalformedJson,
+        Fault::MissingField,
+        Fault::WrongType,
+        Fault::NonFinite,
+        Fault::EmptyDeviceId,
+        Fault::FutureTimestamp,
+        Fault::PastTimestamp,
+        Fault::Duplicate,
+        Fault::Oversized,
+        Fault::OutOfOrder,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::MalformedJson => "malformed_json",
+            Fault::MissingField => "missing_field",
+            Fault::WrongType => "wrong_type",
+            Fault::NonFinite => "non_finite",
+            Fault::EmptyDeviceId => "empty_device_id",
+            Fault::FutureTimestamp => "future_timestamp",
+            Fault::PastTimestamp => "past_timestamp",
+            Fault::Duplicate => "duplicate",
+            Fault::Oversized => "oversized",
+            Fault::OutOfOrder => "out_of_order",
+        }
+    }
+
+    /// Whether the ingestor rejects the message as invalid
+    pub fn rejected(&self) -> bool {
+        matches!(
+            self,
+            Fault::MalformedJson
+                | Fault::MissingField
+                | Fault::WrongType
+                | Fault::NonFinite
+                | Fault::EmptyDeviceId
+        )
+    }
+
+    /// How the ingestor should account for the message
+    pub fn expected(&self) -> &'static str {
+        match self {
+            fault if fault.rejected() => "ingestor_invalid_messages_total",
+            Fault::Duplicate => "ingestor_duplicates_total (second copy)",
+            Fault::Oversized => "rejected by the broker or MQTT packet size limit, else valid",
+            _ => "ingestor_valid_messages_total",
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Fault::ALL
+            .into_iter()
+            .find(|fault| fault.name() == s)
+            .ok_or_else(|| format!("unknown fault: {}", s))
+    }
+}
+
+/// Probability per reading of each fault; at most one is injected per reading
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultConfig {
+    #[serde(flatten)]
+    pub rates: BTreeMap<Fault, f64>,
+    /// Size of oversized payloads
+    #[serde(default = "default_oversized_bytes")]
+    pub oversized_bytes: usize,
+    /// How far far-future and far-past timestamps are shifted
+    #[serde(default = "default_timestamp_skew_days")]
+    pub timestamp_skew_days: i64,
+    /// How far out-of-order readings are moved back, behind earlier ones
+    #[serde(default = "default_out_of_order_secs")]
+    pub out_of_order_secs: i64,
+}
+
+fn default_oversized_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_timestamp_skew_days() -> i64 {
+    3650
+}
+
+fn default_out_of_order_secs() -> i64 {
+    300
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            rates: BTreeMap::new(),
+            oversized_bytes: default_oversized_bytes(),
+            timestamp_skew_days: default_timestamp_skew_days(),
+            out_of_order_secs: default_out_of_order_secs(),
+        }
+    }
+}
+
+/// `malformed_json=0.01,duplicate=0.02,oversized_bytes=131072`
+impl FromStr for FaultConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = FaultConfig::default();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected <fault>=<rate>, got {:?}", entry))?;
+            let invalid = |e: &dyn fmt::Display| format!("invalid value for {}: {}", key, e);
+            match key.trim() {
+                "oversized_bytes" => config.oversized_bytes = value.trim().parse().map_err(|e| invalid(&e))?,
+                "timestamp_skew_days" => {
+                    config.timestamp_skew_days = value.trim().parse().map_err(|e| invalid(&e))?
+                }
+                "out_of_order_secs" => {
+                    config.out_of_order_secs = value.trim().parse().map_err(|e| invalid(&e))?
+                }
+                fault => {
+                    let rate = value.trim().parse().map_err(|e| invalid(&e))?;
+                    config.rates.insert(fault.parse()?, rate);
+                }
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl FaultConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some((fault, _)) = self.rates.iter().find(|(_, rate)| !(0.0..=1.0).contains(*rate)) {
+            return Err(format!("{} rate must be between 0 and 1", fault));
+        }
+        if self.rates.values().sum::<f64>() > 1.0 {
+            return Err("fault rates add up to more than 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.values().all(|rate| *rate == 0.0)
+    }
+}
+
+/// Turns readings into payloads, injecting faults at the configured rates and
+/// counting what it injected
+#[derive(Debug)]
+pub struct FaultInjector {
+    config: FaultConfig,
+    injected: BTreeMap<Fault, u64>,
+    readings: u64,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            config,
+            injected: BTreeMap::new(),
+            readings: 0,
+        }
+    }
+
+    /// Largest payload this injector produces, for the MQTT packet size limit
+    pub fn max_payload_bytes(&self) -> Option<usize> {
+        self.config
+            .rates
+            .get(&Fault::Oversized)
+            .is_some_and(|rate| *rate > 0.0)
+            .then_some(self.config.oversized_bytes)
+    }
+
+    /// Payloads to publish for one reading: usually one, two for a duplicate
+    pub fn payloads(&mut self, telemetry: &Telemetry, rng: &mut impl Rng) -> Vec<String> {
+        self.readings += 1;
+        let fault = self.pick(rng);
+        if let Some(fault) = fault {
+            *self.injected.entry(fault).or_default() += 1;
+        }
+
+        let mut reading = json!(telemetry);
+        let shift = |by: ChronoDuration| json!(telemetry.timestamp + by);
+
+        match fault {
+            None => vec![reading.to_string()],
+            Some(Fault::MalformedJson) => {
+                let mut payload = reading.to_string();
+                // Cutting off at least the closing brace never leaves valid JSON
+                let cut = rng.gen_range(1..payload.len() / 2);
+                payload.truncate(payload.len() - cut);
+                vec![payload]
+            }
+            Some(Fault::MissingField) => {
+                if let Some(object) = reading.as_object_mut() {
+                    object.remove(FIELDS[rng.gen_range(0..FIELDS.len())]);
+                }
+                vec![reading.to_string()]
+            }
+            Some(Fault::WrongType) => {
+                let field = FIELDS[rng.gen_range(0..FIELDS.len())];
+                reading[field] = match &reading[field] {
+                    Value::Number(n) => Value::String(n.to_string()),
+                    _ => json!(rng.gen_range(0..100_000)),
+                };
+                vec![reading.to_string()]
+            }
+            Some(Fault::NonFinite) => {
+                // Not valid JSON, but what some firmware JSON encoders emit
+                let field = NUMERIC_FIELDS[rng.gen_range(0..NUMERIC_FIELDS.len())];
+                let token = ["NaN", "Infinity", "-Infinity"][rng.gen_range(0..3)];
+                reading[field] = Value::String("__non_finite__".to_string());
+                vec![reading.to_string().replace("\"__non_finite__\"", token)]
+            }
+            Some(Fault::EmptyDeviceId) => {
+                reading["device_id"] = Value::String(String::new());
+                vec![reading.to_string()]
+            }
+            Some(Fault::FutureTimestamp) => {
+                reading["timestamp"] = shift(ChronoDuration::days(self.config.timestamp_skew_days));
+                vec![reading.to_string()]
+            }
+            Some(Fault::PastTimestamp) => {
+                reading["timestamp"] = shift(-ChronoDuration::days(self.config.timestamp_skew_days));
+                vec![reading.to_string()]
+            }
+            Some(Fault::OutOfOrder) => {
+                reading["timestamp"] = shift(-ChronoDuration::seconds(self.config.out_of_order_secs));
+                vec![reading.to_string()]
+            }
+            Some(Fault::Duplicate) => {
+                let payload = reading.to_string();
+                vec![payload.clone(), payload]
+            }
+            Some(Fault::Oversized) => {
+                let size = reading.to_string().len() + r#","padding":"""#.len();
+                reading["padding"] = Value::String("x".repeat(self.config.oversized_bytes.saturating_sub(size)));
+                vec![reading.to_string()]
+            }
+        }
+    }
+
+    fn pick(&self, rng: &mut impl Rng) -> Option<Fault> {
+        if self.config.is_empty() {
+            return None;
+        }
+        let roll: f64 = rng.gen();
+        let mut cumulative = 0.0;
+        for (fault, rate) in &self.config.rates {
+            cumulative += rate;
+            if roll < cumulative {
+                return Some(*fault);
+            }
+        }
+        None
+    }
+
+    pub fn summary(&self) -> FaultSummary {
+        FaultSummary {
+            readings: self.readings,
+            injected: self.injected.clone(),
+        }
+    }
+}
+
+/// What was injected, for reconciling against the ingestor's counters
+#[derive(Debug, Clone, Default)]
+pub struct FaultSummary {
+    pub readings: u64,
+    pub injected: BTreeMap<Fault, u64>,
+}
+
+impl FaultSummary {
+    pub fn count(&self, fault: Fault) -> u64 {
+        self.injected.get(&fault).copied().unwrap_or(0)
+    }
+
+    /// Messages the ingestor should count as invalid
+    pub fn expected_invalid(&self) -> u64 {
+        self.injected
+            .iter()
+            .filter(|(fault, _)| fault.rejected())
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+impl fmt::Display for FaultSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: u64 = self.injected.values().sum();
+        writeln!(f, "Injected faults into {} of {} readings", total, self.readings)?;
+        for (fault, count) in &self.injected {
+            writeln!(f, "  {:<18} {:>8}  -> {}", fault.name(), count, fault.expected())?;
+        }
+        writeln!(f, "Expected ingestor_invalid_messages_total increase: {}", self.expected_invalid())?;
+        write!(
+            f,
+            "Expected ingestor_duplicates_total increase: {}",
+            self.count(Fault::Duplicate)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn reading() -> Telemetry {
+        Telemetry::new("dev-1", "2025-10-05T12:00:00Z".parse().unwrap(), 21.5, 55.0, 80.0)
+    }
+
+    fn only(fault: Fault) -> FaultInjector {
+        FaultInjector::new(format!("{}=1", fault).parse().unwrap())
+    }
+
+    /// What the ingestor would make of a payload: `Some` when it parses
+    fn parse(payload: &str) -> Option<Telemetry> {
+        serde_json::from_str::<Telemetry>(payload)
+            .ok()
+            .filter(|t| !t.device_id.is_empty())
+    }
+
+    #[test]
+    fn test_config() {
+        let config: FaultConfig = "malformed_json=0.01, duplicate=0.2,oversized_bytes=1024".parse().unwrap();
+        assert_eq!(config.rates[&Fault::Duplicate], 0.2);
+        assert_eq!(config.oversized_bytes, 1024);
+
+        let config: FaultConfig =
+            serde_json::from_str(r#"{ "wrong_type": 0.5, "out_of_order_secs": 60 }"#).unwrap();
+        assert_eq!(config.rates[&Fault::WrongType], 0.5);
+        assert_eq!(config.out_of_order_secs, 60);
+
+        assert!("bit_flip=0.1".parse::<FaultConfig>().is_err());
+        assert!("duplicate=0.6,wrong_type=0.6".parse::<FaultConfig>().is_err());
+        assert!("duplicate".parse::<FaultConfig>().is_err());
+    }
+
+    #[test]
+    fn test_rejected_faults() {
+        let mut rng = StdRng::seed_from_u64(9);
+        for fault in [
+            Fault::MalformedJson,
+            Fault::MissingField,
+            Fault::WrongType,
+            Fault::NonFinite,
+            Fault::EmptyDeviceId,
+        ] {
+            let mut injector = only(fault);
+            for _ in 0..50 {
+                for payload in injector.payloads(&reading(), &mut rng) {
+                    assert!(parse(&payload).is_none(), "{} produced {}", fault, payload);
+                }
+            }
+            assert_eq!(injector.summary().expected_invalid(), 50);
+        }
+    }
+
+    #[test]
+    fn test_accepted_faults() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let original = reading();
+        let check = |fault: Fault, rng: &mut StdRng| {
+            let payloads = only(fault).payloads(&original, rng);
+            payloads.iter().map(|p| parse(p).unwrap()).collect::<Vec<_>>()
+        };
+
+        let future = check(Fault::FutureTimestamp, &mut rng);
+        assert_eq!(future[0].timestamp.format("%Y").to_string(), "2035");
+        let past = check(Fault::PastTimestamp, &mut rng);
+        assert_eq!(past[0].timestamp.format("%Y").to_string(), "2015");
+        let late = check(Fault::OutOfOrder, &mut rng);
+        assert_eq!(original.timestamp - late[0].timestamp, ChronoDuration::seconds(300));
+
+        let duplicates = only(Fault::Duplicate).payloads(&original, &mut rng);
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(duplicates[0], duplicates[1]);
+
+        let mut injector = FaultInjector::new("oversized=1,oversized_bytes=4096".parse().unwrap());
+        assert_eq!(injector.max_payload_bytes(), Some(4096));
+        let oversized = injector.payloads(&original, &mut rng);
+        assert_eq!(oversized[0].len(), 4096);
+        assert_eq!(parse(&oversized[0]).unwrap().battery, 80.0);
+    }
+
+    #[test]
+    fn test_rates_and_summary() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut injector = FaultInjector::new("malformed_json=0.1,duplicate=0.05".parse().unwrap());
+        let published: usize = (0..10_000)
+            .map(|_| injector.payloads(&reading(), &mut rng).len())
+            .sum();
+
+        let summary = injector.summary();
+        assert_eq!(summary.readings, 10_000);
+        assert!((900..1100).contains(&summary.count(Fault::MalformedJson)));
+        assert!((400..600).contains(&summary.count(Fault::Duplicate)));
+        assert_eq!(published as u64, 10_000 + summary.count(Fault::Duplicate));
+        assert!(summary.to_string().contains("malformed_json"));
+
+        let mut clean = FaultInjector::new(FaultConfig::default());
+        assert_eq!(parse(&clean.payloads(&reading(), &mut rng)[0]).unwrap().device_id, "dev-1");
+        assert!(clean.summary().injected.is_empty());
+    }
+}
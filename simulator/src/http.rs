@@ -0,0 +1,122 @@
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use reqwest::{Client, Url};
+use serde_json::Value;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client of the ingestor's REST API. Clones share one connection pool, so
+/// create it once per run and pass it around.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// API URL without a trailing slash, e.g. `http://ingestor:8080/base`
+    base_url: String,
+}
+
+impl HttpClient {
+    pub fn new(url: &str, api_key: Option<String>) -> Result<Self, String> {
+        let parsed = Url::parse(url).map_err(|e| format!("invalid API URL {:?}: {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+            return Err(format!("invalid API URL {:?} (expected http:// or https://)", url));
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        if let Some(key) = api_key {
+            let mut value = HeaderValue::from_str(&key).map_err(|_| "invalid API key".to_string())?;
+            value.set_sensitive(true);
+            headers.insert("x-api-key", value);
+        }
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .default_headers(headers)
+            .build()
+            .map_err(|e| format!("cannot create HTTP client: {}", e))?;
+        Ok(Self {
+            client,
+            base_url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// `GET path?query`, expecting a 2xx JSON response
+    pub async fn get_json(&self, path: &str, query: &[(&str, String)]) -> Result<Value, String> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| format!("GET {} failed: {}", path, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("GET {} returned {}: {}", path, status, body));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| format!("GET {} returned invalid JSON: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_new() {
+        let client = HttpClient::new("http://ingestor:8080/base/", None).unwrap();
+        assert_eq!(client.base_url, "http://ingestor:8080/base");
+        assert!(HttpClient::new("https://localhost", None).is_ok());
+        assert!(HttpClient::new("ftp://localhost", None).is_err());
+        assert!(HttpClient::new("localhost:8080", None).is_err());
+    }
+
+    /// Serves one canned response and returns the request it received
+    async fn serve_once(response: &'static [u8]) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/base", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            stream.write_all(response).await.unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_get_json() {
+        let (url, server) = serve_once(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\n{\"a\r\n4\r\n\":1}\r\n0\r\n\r\n",
+        )
+        .await;
+        let client = HttpClient::new(&url, Some("iot_key".to_string())).unwrap();
+
+        let body = client
+            .get_json("/api/v1/telemetry", &[("start", "2025-10-05T12:00:00.5+00:00".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(body, serde_json::json!({ "a": 1 }));
+
+        let request = server.await.unwrap().to_ascii_lowercase();
+        assert!(request.starts_with("get /base/api/v1/telemetry?start=2025-10-05t12%3a00%3a00.5%2b00%3a00 "));
+        assert!(request.contains("x-api-key: iot_key\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_get_json_error_status() {
+        let (url, _server) = serve_once(
+            b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 15\r\nConnection: close\r\n\r\n{\"code\":\"401\"}\n",
+        )
+        .await;
+        let client = HttpClient::new(&url, None).unwrap();
+
+        let error = client.get_json("/api/v1/telemetry", &[]).await.unwrap_err();
+        assert!(error.contains("401"), "{}", error);
+    }
+}
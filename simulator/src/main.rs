@@ -1,136 +1,261 @@
-mod telemetry;
+mod cli;
+mod device;
+mod faults;
+mod http;
+mod pool;
+mod publisher;
+mod replay;
+mod scenario;
+mod stats;
+mod verify;
 
 use chrono::Utc;
-use std::env;
-use telemetry::Telemetry;
-use rand::Rng;
-use rumqttc::{AsyncClient, MqttOptions, QoS};
+use clap::Parser;
+use cli::{render_topic, Cli, Command, MqttArgs, ReportFormat, ScenarioArgs};
+use faults::FaultInjector;
+use pool::{ConnectionPool, Connections};
+use publisher::{Pacer, Publisher};
+use rand::{rngs::StdRng, SeedableRng};
+use scenario::{Scenario, SimClock};
+use stats::{Report, Stats};
+use std::future::Future;
 use std::time::Duration;
+use tokio::time::Instant;
 use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-#[tokio::main]
-async fn main() {
-    let mqtt_broker = env::var("MQTT_BROKER").unwrap_or_else(|_| "localhost".to_string());
-    let mqtt_port: u16 = env::var("MQTT_PORT")
-        .unwrap_or_else(|_| "1883".to_string())
-        .parse()
-        .unwrap_or(1883);
-    let rate: u64 = env::var("RATE")
-        .unwrap_or_else(|_| "1000".to_string())
-        .parse()
-        .unwrap_or(1000);
-    let num_devices: usize = env::var("DEVICES")
-        .unwrap_or_else(|_| "100".to_string())
-        .parse()
-        .unwrap_or(100);
-
-    // Initialize logging
-    tracing_subscriber::fmt::init();
-
-    info!("Starting IoT Simulator");
-    info!("Broker: {}:{}, Rate: {} msg/s, Devices: {}", mqtt_broker, mqtt_port, rate, num_devices);
-
-    // Generate client ID 
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
-    let client_id = format!("sim-{}", rng.gen::<u32>());
-
-    // Connect to MQTT broker
-    let mut mqtt_options = MqttOptions::new(&client_id, &mqtt_broker, mqtt_port);
-    mqtt_options.set_keep_alive(Duration::from_secs(30));
-    mqtt_options.set_clean_session(true);
-
-    let (client, mut eventloop) = AsyncClient::new(mqtt_options, 20000);
-
-    // Spawn eventloop handler
-    tokio::spawn(async move {
-        loop {
-            match eventloop.poll().await {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("MQTT eventloop error: {}", e);
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
-            }
-        }
-    });
-
-    tokio::time::sleep(Duration::from_secs(2)).await;
+/// How long to wait on exit for the broker to acknowledge what was published
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
 
-    info!("Connected to MQTT broker, starting to publish telemetry");
-
-    let mut rng = rand::thread_rng();
-    let mut counter = 0u64;
-
-    const BURST_SIZE: usize = 200;
-    let burst_interval = Duration::from_millis((BURST_SIZE as u64 * 1000) / rate);
-    
-    info!("Publishing in bursts of {} messages every {:?}", BURST_SIZE, burst_interval);
+/// When a generating command stops and whether it reports along the way
+struct Limits {
+    /// Hold `--rate`; `burst` publishes as fast as it can
+    paced: bool,
+    count: Option<u64>,
+    duration: Option<Duration>,
+    report_every: Option<Duration>,
+}
 
-    loop {
-        let burst_start = std::time::Instant::now();
+#[tokio::main]
+async fn main() {
+    // Logs go to stderr so the final report on stdout can be piped
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 
-        for _ in 0..BURST_SIZE {
-            let device_id = format!("dev-{}", counter % num_devices as u64);
-            let telemetry = generate_telemetry(&mut rng, device_id);
+    let cli = Cli::parse();
+    let format = cli.format;
+    let mqtt = cli.mqtt.clone();
 
-            let topic = format!("telemetry/{}", telemetry.device_id);
-            let payload = match serde_json::to_string(&telemetry) {
-                Ok(p) => p,
-                Err(e) => {
-                    error!("Failed to serialize telemetry: {}", e);
-                    continue;
-                }
+    let result = match cli.command() {
+        Command::Run(args) => {
+            let limits = Limits {
+                paced: true,
+                count: args.count,
+                duration: args.duration,
+                report_every: None,
             };
-
-            match client.publish(&topic, QoS::AtLeastOnce, false, payload).await {
-                Ok(_) => {
-                    counter += 1;
-                }
-                Err(e) => {
-                    warn!("Failed to publish: {}", e);
+            generate("run", &mqtt, args.scenario, limits, format).await
+        }
+        Command::Burst(args) => {
+            let limits = Limits {
+                paced: false,
+                count: Some(args.count),
+                duration: None,
+                report_every: None,
+            };
+            generate("burst", &mqtt, args.scenario, limits, format).await
+        }
+        Command::Soak(args) => {
+            let limits = Limits {
+                paced: true,
+                count: None,
+                duration: Some(args.duration),
+                report_every: Some(args.report_every),
+            };
+            generate("soak", &mqtt, args.scenario, limits, format).await
+        }
+        Command::Replay(args) => {
+            let publisher = Publisher::connect(&mqtt, None).await;
+            let mut stats = Stats::new();
+            let result = until_interrupted(replay::replay(&publisher, &args, &mut stats)).await;
+            let mut report = Report::new("replay", &stats, None, None, None);
+            let acked = publisher.shutdown(stats.published, FLUSH_TIMEOUT).await;
+            report.acknowledged = (mqtt.qos > 0).then_some(acked);
+            result.unwrap_or(Ok(())).map(|()| report)
+        }
+        Command::Verify(args) => {
+            let publisher = Publisher::connect(&mqtt, None).await;
+            let result = until_interrupted(verify::verify(&publisher, &args)).await;
+            publisher.shutdown(0, Duration::ZERO).await;
+            match result {
+                Some(Ok(report)) => {
+                    report.print(format);
+                    std::process::exit(if report.passed { 0 } else { 1 });
                 }
+                Some(Err(e)) => Err(e),
+                None => Err("verification interrupted".to_string()),
             }
         }
-        
-        // Log progress periodically
-        if counter % 10000 == 0 {
-            info!("Published {} messages", counter);
+    };
+
+    match result {
+        Ok(report) => report.print(format),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
         }
+    }
+}
 
-        let elapsed = burst_start.elapsed();
-        if elapsed < burst_interval {
-            tokio::time::sleep(burst_interval - elapsed).await;
-        } else if elapsed > burst_interval * 2 {
-            warn!("Burst took {:?}, target was {:?} - system may be overloaded", elapsed, burst_interval);
+/// Runs `future` until it finishes or Ctrl+C is pressed; `None` when interrupted
+async fn until_interrupted<T>(future: impl Future<Output = T>) -> Option<T> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = tokio::signal::ctrl_c() => {
+            info!("Received shutdown signal");
+            None
         }
     }
 }
 
-fn generate_telemetry(rng: &mut impl Rng, device_id: String) -> Telemetry {
-    let temperature = if rng.gen_bool(0.05) {
-        rng.gen_range(-50.0..100.0) // 5% outliers
-    } else {
-        rng.gen_range(15.0..35.0) // Normal range
+/// Publishes simulated readings until a limit is reached or the user stops it
+async fn generate(
+    command: &'static str,
+    mqtt: &MqttArgs,
+    args: ScenarioArgs,
+    limits: Limits,
+    format: ReportFormat,
+) -> Result<Report, String> {
+    let mut scenario = match &args.scenario {
+        Some(path) => {
+            let scenario = Scenario::load(path)?;
+            info!("Loaded scenario {} with {} fleets", path.display(), scenario.fleets.len());
+            scenario
+        }
+        None => Scenario::default_fleet(args.devices),
     };
 
-    let humidity = if rng.gen_bool(0.05) {
-        rng.gen_range(0.0..100.0) // 5% outliers
-    } else {
-        rng.gen_range(30.0..80.0) // Normal range
+    // Always seeded, so any run can be repeated with the seed from its report
+    let seed = args.seed.or(scenario.seed).unwrap_or_else(rand::random);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut devices = scenario.devices(&mut rng);
+    if devices.is_empty() {
+        return Err("no devices to simulate".to_string());
+    }
+    let clock = SimClock::new(
+        args.start.unwrap_or_else(Utc::now),
+        scenario.time_scale,
+        args.rate,
+    );
+
+    let fault_config = args.faults.or(scenario.faults.take()).unwrap_or_default();
+    if !fault_config.is_empty() {
+        let rates: Vec<String> = fault_config
+            .rates
+            .iter()
+            .map(|(fault, rate)| format!("{}={}", fault, rate))
+            .collect();
+        info!("Injecting faults: {}", rates.join(","));
+    }
+    let mut injector = FaultInjector::new(fault_config);
+
+    info!("Starting IoT Simulator ({})", command);
+    info!(
+        "Broker: {}:{}, Rate: {} readings/s, Devices: {}, Time scale: {}x, Seed: {}",
+        mqtt.broker,
+        mqtt.port,
+        args.rate,
+        devices.len(),
+        scenario.time_scale,
+        seed
+    );
+
+    let connections = match args.connections.connections.group_size() {
+        None => {
+            let publisher = Publisher::connect(mqtt, injector.max_payload_bytes()).await;
+            info!("Connected to MQTT broker, starting to publish telemetry");
+            Connections::Shared(publisher)
+        }
+        Some(group_size) => {
+            let device_ids: Vec<String> = devices.iter().map(|device| device.id.clone()).collect();
+            let pool = ConnectionPool::start(
+                mqtt,
+                &args.connections,
+                &device_ids,
+                group_size,
+                injector.max_payload_bytes(),
+                seed,
+            );
+            Connections::Pool(pool)
+        }
     };
 
-    let battery = if rng.gen_bool(0.02) {
-        rng.gen_range(0.0..20.0) // 2% low battery
-    } else {
-        rng.gen_range(20.0..100.0) // Normal range
+    let mut stats = Stats::new();
+    let report = |stats: &Stats, injector: &FaultInjector, connections: &Connections| {
+        let acknowledged = (mqtt.qos > 0).then(|| connections.acked());
+        let mut report = Report::new(command, stats, Some(seed), acknowledged, Some(injector.summary()));
+        report.connections = connections.summary();
+        report
     };
 
-    Telemetry {
-        device_id,
-        timestamp: Utc::now(),
-        temperature,
-        humidity,
-        battery,
-    }
+    let publishing = async {
+        let mut pacer = Pacer::new(args.rate);
+        let deadline = limits.duration.map(|duration| Instant::now() + duration);
+        let mut next_report = limits.report_every.map(|every| Instant::now() + every);
+        let mut next_log = 10000u64;
+        let mut turn = 0u64;
+
+        'publishing: loop {
+            // Each device takes its turn; offline devices publish nothing and
+            // reconnecting ones their backlog
+            let index = (turn % devices.len() as u64) as usize;
+            let now = clock.at_turn(turn);
+            turn += 1;
+
+            for telemetry in devices[index].sample(now, Utc::now(), &mut rng) {
+                if limits.count.is_some_and(|count| stats.published + stats.failed >= count) {
+                    break 'publishing;
+                }
+                stats.readings += 1;
+                let topic = render_topic(&args.topic, &telemetry.device_id, &devices[index].fleet);
+                for payload in injector.payloads(&telemetry, &mut rng) {
+                    match connections.publish(index, &topic, payload).await {
+                        Ok(true) => stats.published += 1,
+                        Ok(false) => {}
+                        Err(e) => {
+                            stats.failed += 1;
+                            warn!("Failed to publish: {}", e);
+                        }
+                    }
+                }
+            }
+
+            // Log progress periodically
+            if stats.published >= next_log {
+                info!("Published {} messages", stats.published);
+                next_log += 10000;
+            }
+            if let Some(at) = next_report.filter(|at| Instant::now() >= *at) {
+                report(&stats, &injector, &connections).in_progress().print(format);
+                next_report = limits.report_every.map(|every| at + every);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            if limits.paced {
+                pacer.tick().await;
+            } else if turn.is_multiple_of(1000) {
+                // Let the event loop drain the request queue
+                tokio::task::yield_now().await;
+            }
+        }
+    };
+    until_interrupted(publishing).await;
+
+    let mut report = report(&stats, &injector, &connections);
+    let acked = connections.shutdown(stats.published, FLUSH_TIMEOUT).await;
+    report.acknowledged = (mqtt.qos > 0).then_some(acked);
+    Ok(report)
 }
@@ -0,0 +1,466 @@
+use crate::cli::{ConnectionArgs, MqttArgs};
+use crate::publisher::{mqtt_options, Publisher};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rumqttc::{AsyncClient, ClientError, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Requests queued per connection before publishes to it fail; kept small
+/// because tens of thousands of connections each hold one
+const QUEUE_PER_DEVICE: usize = 64;
+const MAX_QUEUE: usize = 20000;
+
+/// How long to wait for DISCONNECT to go out when churning or shutting down
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Pause after a connection error before the event loop reconnects
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Where simulated devices publish: one shared connection or a pool
+pub enum Connections {
+    Shared(Publisher),
+    Pool(ConnectionPool),
+}
+
+impl Connections {
+    /// Publishes on behalf of device `device`; `Ok(false)` when its connection
+    /// is down and the reading is lost, as it would be on a real device
+    pub async fn publish(&self, device: usize, topic: &str, payload: String) -> Result<bool, ClientError> {
+        match self {
+            Connections::Shared(publisher) => publisher.publish(topic, payload).await.map(|()| true),
+            Connections::Pool(pool) => pool.publish(device, topic, payload),
+        }
+    }
+
+    pub fn acked(&self) -> u64 {
+        match self {
+            Connections::Shared(publisher) => publisher.acked(),
+            Connections::Pool(pool) => pool.counters.acked.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn summary(&self) -> Option<ConnectionSummary> {
+        match self {
+            Connections::Shared(_) => None,
+            Connections::Pool(pool) => Some(pool.summary()),
+        }
+    }
+
+    /// Waits for outstanding acknowledgements and disconnects everything;
+    /// returns the final ack count
+    pub async fn shutdown(self, published: u64, timeout: Duration) -> u64 {
+        match self {
+            Connections::Shared(publisher) => publisher.shutdown(published, timeout).await,
+            Connections::Pool(pool) => pool.shutdown(published, timeout).await,
+        }
+    }
+}
+
+/// Which connection each device uses and the client id of each connection
+#[derive(Debug, PartialEq)]
+struct Plan {
+    client_ids: Vec<String>,
+    device_connection: Vec<usize>,
+}
+
+impl Plan {
+    fn new(device_ids: &[String], group_size: usize, prefix: &str) -> Self {
+        let device_connection = (0..device_ids.len()).map(|i| i / group_size).collect();
+        let client_ids = if group_size == 1 {
+            device_ids.iter().map(|id| format!("{}{}", prefix, id)).collect()
+        } else {
+            (0..device_ids.len().div_ceil(group_size))
+                .map(|group| format!("{}group-{}", prefix, group))
+                .collect()
+        };
+        Self {
+            client_ids,
+            device_connection,
+        }
+    }
+}
+
+/// Lifetime totals across all connections of a pool
+#[derive(Debug, Default)]
+struct Counters {
+    acked: AtomicU64,
+    /// Connections currently holding a session
+    connected: AtomicU64,
+    connects: AtomicU64,
+    disconnects: AtomicU64,
+    drops: AtomicU64,
+    errors: AtomicU64,
+    offline_readings: AtomicU64,
+}
+
+/// Connection stats for the report
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionSummary {
+    pub connections: usize,
+    pub connected: u64,
+    /// Sessions accepted by the broker, including reconnects
+    pub connects: u64,
+    /// Churned with a clean DISCONNECT
+    pub disconnects: u64,
+    /// Churned by dropping the socket, which fires the last will
+    pub drops: u64,
+    pub errors: u64,
+    /// Readings lost because their device's connection was down
+    pub offline_readings: u64,
+}
+
+impl fmt::Display for ConnectionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  connections:   {} ({} connected)", self.connections, self.connected)?;
+        writeln!(f, "  connects:      {}", self.connects)?;
+        writeln!(f, "  disconnects:   {} clean, {} dropped", self.disconnects, self.drops)?;
+        writeln!(f, "  conn errors:   {}", self.errors)?;
+        write!(f, "  offline:       {} readings lost", self.offline_readings)
+    }
+}
+
+/// Churn settings one connection needs
+#[derive(Debug, Clone, Copy)]
+struct Churn {
+    every: Duration,
+    offline: Duration,
+    unclean: f64,
+}
+
+/// Many MQTT connections, each kept alive by its own task
+pub struct ConnectionPool {
+    /// Client of each connection while it is up; `None` while churned offline
+    clients: Vec<Arc<Mutex<Option<AsyncClient>>>>,
+    device_connection: Vec<usize>,
+    qos: QoS,
+    counters: Arc<Counters>,
+    stop: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ConnectionPool {
+    /// Starts one task per connection. Connections open at `--connect-rate`
+    /// in the background, so early readings of later devices may be lost.
+    /// Churn timing is drawn from `seed`, the seed of the run.
+    pub fn start(
+        mqtt: &MqttArgs,
+        args: &ConnectionArgs,
+        device_ids: &[String],
+        group_size: usize,
+        max_payload: Option<usize>,
+        seed: u64,
+    ) -> Self {
+        let prefix = mqtt.client_id.as_deref().unwrap_or("sim-");
+        let plan = Plan::new(device_ids, group_size, prefix);
+        let queue = (group_size * QUEUE_PER_DEVICE).min(MAX_QUEUE);
+        let churn = args.churn_every.map(|every| Churn {
+            every,
+            offline: args.churn_offline,
+            unclean: args.churn_unclean,
+        });
+        info!(
+            "Opening {} MQTT connections at {}/s ({} devices each)",
+            plan.client_ids.len(),
+            args.connect_rate,
+            group_size
+        );
+
+        let counters = Arc::new(Counters::default());
+        let (stop, stopped) = watch::channel(false);
+        let mut clients = Vec::with_capacity(plan.client_ids.len());
+        let mut tasks = Vec::with_capacity(plan.client_ids.len());
+        for (index, client_id) in plan.client_ids.into_iter().enumerate() {
+            let mut options = mqtt_options(mqtt, client_id.clone(), max_payload);
+            let status_topic = args
+                .will_topic
+                .as_ref()
+                .map(|template| template.replace("{client_id}", &client_id));
+            if let Some(topic) = &status_topic {
+                options.set_last_will(LastWill::new(topic, status(&client_id, false), mqtt.qos(), true));
+            }
+            let client = Arc::new(Mutex::new(None));
+            let connection = Connection {
+                client_id,
+                options,
+                queue,
+                qos: mqtt.qos(),
+                status_topic,
+                churn,
+                rng: connection_rng(seed, index),
+                client: client.clone(),
+                counters: counters.clone(),
+                stopped: stopped.clone(),
+            };
+            let delay = Duration::from_secs_f64(index as f64 / args.connect_rate as f64);
+            tasks.push(tokio::spawn(connection.run(delay)));
+            clients.push(client);
+        }
+
+        Self {
+            clients,
+            device_connection: plan.device_connection,
+            qos: mqtt.qos(),
+            counters,
+            stop,
+            tasks,
+        }
+    }
+
+    /// Queues without waiting, so one stalled connection cannot hold up the
+    /// rest of the fleet; a full queue fails the publish
+    fn publish(&self, device: usize, topic: &str, payload: String) -> Result<bool, ClientError> {
+        let client = &self.clients[self.device_connection[device]];
+        match &*client.lock().unwrap() {
+            Some(client) => client.try_publish(topic, self.qos, false, payload).map(|()| true),
+            None => {
+                self.counters.offline_readings.fetch_add(1, Ordering::Relaxed);
+                Ok(false)
+            }
+        }
+    }
+
+    fn summary(&self) -> ConnectionSummary {
+        let counters = &self.counters;
+        ConnectionSummary {
+            connections: self.clients.len(),
+            connected: counters.connected.load(Ordering::Relaxed),
+            connects: counters.connects.load(Ordering::Relaxed),
+            disconnects: counters.disconnects.load(Ordering::Relaxed),
+            drops: counters.drops.load(Ordering::Relaxed),
+            errors: counters.errors.load(Ordering::Relaxed),
+            offline_readings: counters.offline_readings.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn shutdown(self, published: u64, timeout: Duration) -> u64 {
+        let acked = || self.counters.acked.load(Ordering::Relaxed);
+        let deadline = Instant::now() + timeout;
+        if self.qos != QoS::AtMostOnce {
+            while acked() < published && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            if acked() < published {
+                warn!(
+                    "{} of {} messages unacknowledged after {:?}",
+                    published - acked(),
+                    published,
+                    timeout
+                );
+            }
+        }
+
+        let _ = self.stop.send(true);
+        let deadline = Instant::now() + DISCONNECT_TIMEOUT * 2;
+        for task in self.tasks {
+            if tokio::time::timeout_at(deadline, task).await.is_err() {
+                break;
+            }
+        }
+        acked()
+    }
+}
+
+/// One connection's settings and the state it shares with the pool
+struct Connection {
+    client_id: String,
+    options: MqttOptions,
+    queue: usize,
+    qos: QoS,
+    status_topic: Option<String>,
+    churn: Option<Churn>,
+    rng: StdRng,
+    client: Arc<Mutex<Option<AsyncClient>>>,
+    counters: Arc<Counters>,
+    stopped: watch::Receiver<bool>,
+}
+
+/// Why a session ended
+enum Ended {
+    Churned,
+    Stopped,
+}
+
+impl Connection {
+    /// Connects after `delay`, then keeps a session up until the pool stops,
+    /// disconnecting and reconnecting when churn is enabled
+    async fn run(mut self, delay: Duration) {
+        if !self.sleep(delay).await {
+            return;
+        }
+        loop {
+            let (client, eventloop) = AsyncClient::new(self.options.clone(), self.queue);
+            *self.client.lock().unwrap() = Some(client.clone());
+            let ended = self.session(&client, eventloop).await;
+            *self.client.lock().unwrap() = None;
+
+            let offline = match (ended, self.churn) {
+                (Ended::Churned, Some(churn)) => exponential(churn.offline, &mut self.rng),
+                _ => return,
+            };
+            if !self.sleep(offline).await {
+                return;
+            }
+        }
+    }
+
+    /// Polls the event loop until the session is churned or the pool stops
+    async fn session(&mut self, client: &AsyncClient, mut eventloop: EventLoop) -> Ended {
+        let churn_at = self.churn.map(|churn| Instant::now() + exponential(churn.every, &mut self.rng));
+        let mut online = false;
+
+        let ended = loop {
+            tokio::select! {
+                event = eventloop.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        online = true;
+                        self.counters.connects.fetch_add(1, Ordering::Relaxed);
+                        self.counters.connected.fetch_add(1, Ordering::Relaxed);
+                        if let Some(topic) = &self.status_topic {
+                            let _ = client.try_publish(topic, self.qos, true, status(&self.client_id, true));
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::PubAck(_) | Packet::PubComp(_))) => {
+                        self.counters.acked.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if std::mem::take(&mut online) {
+                            self.counters.connected.fetch_sub(1, Ordering::Relaxed);
+                        }
+                        // Only the first error is worth a warning; with thousands of
+                        // connections the rest would drown the log
+                        if self.counters.errors.fetch_add(1, Ordering::Relaxed) == 0 {
+                            warn!("{}: MQTT eventloop error: {} (further errors logged at debug)", self.client_id, e);
+                        } else {
+                            debug!("{}: MQTT eventloop error: {}", self.client_id, e);
+                        }
+                        if !self.sleep(RECONNECT_BACKOFF).await {
+                            return Ended::Stopped;
+                        }
+                    }
+                },
+                _ = sleep_until(churn_at) => break Ended::Churned,
+                _ = self.stopped.changed() => break Ended::Stopped,
+            }
+        };
+
+        if std::mem::take(&mut online) {
+            self.counters.connected.fetch_sub(1, Ordering::Relaxed);
+        } else {
+            return ended;
+        }
+        let unclean = match (&ended, self.churn) {
+            (Ended::Churned, Some(churn)) => self.rng.gen_bool(churn.unclean),
+            _ => false,
+        };
+        if unclean {
+            // Dropping the event loop closes the socket without DISCONNECT
+            self.counters.drops.fetch_add(1, Ordering::Relaxed);
+            return ended;
+        }
+
+        if let Some(topic) = &self.status_topic {
+            // A clean disconnect discards the will, so announce it ourselves
+            let _ = client.try_publish(topic, self.qos, true, status(&self.client_id, false));
+        }
+        let _ = client.try_disconnect();
+        let _ = tokio::time::timeout(DISCONNECT_TIMEOUT, async {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                    Ok(Event::Incoming(Packet::PubAck(_) | Packet::PubComp(_))) => {
+                        self.counters.acked.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(_) => {}
+                }
+            }
+        })
+        .await;
+        if matches!(ended, Ended::Churned) {
+            self.counters.disconnects.fetch_add(1, Ordering::Relaxed);
+        }
+        ended
+    }
+
+    /// Sleeps unless the pool stops first; false when stopped
+    async fn sleep(&mut self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => !*self.stopped.borrow(),
+            _ = self.stopped.changed() => false,
+        }
+    }
+}
+
+async fn sleep_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Random stream of one connection, derived from the run seed so a seeded
+/// run churns the same way again; offset so connection 0 does not replay the
+/// readings' stream
+fn connection_rng(seed: u64, index: usize) -> StdRng {
+    StdRng::seed_from_u64(seed.wrapping_add(index as u64 + 1))
+}
+
+/// Random duration with the given mean, so churn is spread out rather than
+/// every connection cycling in step
+fn exponential(mean: Duration, rng: &mut impl Rng) -> Duration {
+    let u: f64 = rng.gen_range(0.0..1.0);
+    mean.mul_f64(-(1.0 - u).ln())
+}
+
+fn status(client_id: &str, online: bool) -> String {
+    serde_json::json!({
+        "client_id": client_id,
+        "status": if online { "online" } else { "offline" },
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let devices: Vec<String> = (0..5).map(|i| format!("dev-{}", i)).collect();
+
+        let plan = Plan::new(&devices, 1, "sim-");
+        assert_eq!(plan.client_ids[3], "sim-dev-3");
+        assert_eq!(plan.device_connection, vec![0, 1, 2, 3, 4]);
+
+        let plan = Plan::new(&devices, 2, "load-");
+        assert_eq!(plan.client_ids, vec!["load-group-0", "load-group-1", "load-group-2"]);
+        assert_eq!(plan.device_connection, vec![0, 0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_exponential_mean() {
+        let mean = Duration::from_secs(60);
+        let mut rng = StdRng::seed_from_u64(7);
+        let total: f64 = (0..20000).map(|_| exponential(mean, &mut rng).as_secs_f64()).sum();
+        assert!((total / 20000.0 - 60.0).abs() < 3.0);
+    }
+
+    #[test]
+    fn test_churn_follows_seed() {
+        let mean = Duration::from_secs(60);
+        let draws = |seed, index| {
+            let mut rng = connection_rng(seed, index);
+            (0..5).map(|_| exponential(mean, &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(42, 3), draws(42, 3));
+        assert_ne!(draws(42, 3), draws(42, 4));
+        assert_ne!(draws(42, 3), draws(43, 3));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::cli::MqttArgs;
+use rumqttc::{AsyncClient, ClientError, Event, MqttOptions, Outgoing, Packet, QoS};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+/// How long to wait for the broker's CONNACK before publishing anyway
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for DISCONNECT to go out on shutdown
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One MQTT connection, counting the broker's acknowledgements
+pub struct Publisher {
+    client: AsyncClient,
+    qos: QoS,
+    acked: Arc<AtomicU64>,
+    eventloop: JoinHandle<()>,
+}
+
+impl Publisher {
+    /// Connects and waits for the broker to accept the session. `max_payload`
+    /// raises the MQTT packet size limit (10 KiB by default) for large payloads.
+    pub async fn connect(args: &MqttArgs, max_payload: Option<usize>) -> Self {
+        let client_id = args
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("sim-{}", rand::random::<u32>()));
+        let mqtt_options = mqtt_options(args, client_id, max_payload);
+
+        let (client, mut eventloop) = AsyncClient::new(mqtt_options, 20000);
+        let acked = Arc::new(AtomicU64::new(0));
+        let connected = Arc::new(Notify::new());
+
+        let eventloop = {
+            let acked = acked.clone();
+            let connected = connected.clone();
+            tokio::spawn(async move {
+                loop {
+                    match eventloop.poll().await {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => connected.notify_one(),
+                        Ok(Event::Incoming(Packet::PubAck(_) | Packet::PubComp(_))) => {
+                            acked.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                        Ok(_) => {}
+                        Err(e) => {
+                            error!("MQTT eventloop error: {}", e);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            })
+        };
+
+        if tokio::time::timeout(CONNECT_TIMEOUT, connected.notified())
+            .await
+            .is_err()
+        {
+            warn!(
+                "No CONNACK from {}:{} after {:?}, publishing anyway",
+                args.broker, args.port, CONNECT_TIMEOUT
+            );
+        }
+
+        Self {
+            client,
+            qos: args.qos(),
+            acked,
+            eventloop,
+        }
+    }
+
+    pub async fn publish(&self, topic: &str, payload: impl Into<Vec<u8>>) -> Result<(), ClientError> {
+        self.client.publish(topic, self.qos, false, payload).await
+    }
+
+    /// Messages acknowledged by the broker so far (always 0 at QoS 0)
+    pub fn acked(&self) -> u64 {
+        self.acked.load(Ordering::Relaxed)
+    }
+
+    /// Waits at most `timeout` for the broker to acknowledge `published`
+    /// messages (QoS 1 and 2), then disconnects; returns the final ack count
+    pub async fn shutdown(self, published: u64, timeout: Duration) -> u64 {
+        let deadline = tokio::time::Instant::now() + timeout;
+        if self.qos != QoS::AtMostOnce {
+            while self.acked() < published && tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            if self.acked() < published {
+                warn!(
+                    "{} of {} messages unacknowledged after {:?}",
+                    published - self.acked(),
+                    published,
+                    timeout
+                );
+            }
+        }
+
+        // DISCONNECT goes out after everything queued before it
+        if let Err(e) = self.client.disconnect().await {
+            warn!("Failed to queue disconnect: {}", e);
+        }
+        let _ = tokio::time::timeout(DISCONNECT_TIMEOUT, self.eventloop).await;
+        self.acked.load(Ordering::Relaxed)
+    }
+}
+
+/// Connection options shared by the single publisher and the connection pool
+pub fn mqtt_options(args: &MqttArgs, client_id: String, max_payload: Option<usize>) -> MqttOptions {
+    let mut mqtt_options = MqttOptions::new(client_id, &args.broker, args.port);
+    mqtt_options.set_keep_alive(Duration::from_secs(args.keepalive.as_secs()));
+    mqtt_options.set_clean_session(true);
+    if let Some(max_payload) = max_payload {
+        // Leave room for the topic and headers
+        let max_packet = max_payload + 1024;
+        mqtt_options.set_max_packet_size(max_packet, max_packet);
+    }
+    mqtt_options
+}
+
+/// Holds a target rate by letting `tick` sleep after each burst of events
+pub struct Pacer {
+    burst: u64,
+    interval: Duration,
+    ticks: u64,
+    burst_start: tokio::time::Instant,
+}
+
+impl Pacer {
+    pub fn new(rate: u64) -> Self {
+        // About ten bursts per second, at most 200 events each
+        let burst = (rate / 10).clamp(1, 200);
+        Self {
+            burst,
+            interval: Duration::from_secs_f64(burst as f64 / rate.max(1) as f64),
+            ticks: 0,
+            burst_start: tokio::time::Instant::now(),
+        }
+    }
+
+    pub async fn tick(&mut self) {
+        self.ticks += 1;
+        if !self.ticks.is_multiple_of(self.burst) {
+            return;
+        }
+        let elapsed = self.burst_start.elapsed();
+        if elapsed < self.interval {
+            tokio::time::sleep(self.interval - elapsed).await;
+        } else if elapsed > self.interval * 2 {
+            warn!(
+                "Burst took {:?}, target was {:?} - system may be overloaded",
+                elapsed, self.interval
+            );
+        }
+        self.burst_start = tokio::time::Instant::now();
+    }
+}
@@ -0,0 +1,332 @@
+use crate::cli::{render_topic, InputFormat, ReplayArgs, TimestampMode};
+use crate::publisher::{Pacer, Publisher};
+use crate::stats::Stats;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::{Map, Value};
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// CSV columns that are part of the wire format; others (`id`, `inserted_at`,
+/// `tenant_id`, ...) are dropped
+const NUMERIC_COLUMNS: [&str; 3] = ["temperature", "humidity", "battery"];
+const TEXT_COLUMNS: [&str; 2] = ["device_id", "priority"];
+
+/// One recorded message
+#[derive(Debug, Clone, PartialEq)]
+struct Record {
+    /// Parsed payload; `None` for lines that are not a JSON object, which are
+    /// replayed byte for byte
+    json: Option<Map<String, Value>>,
+    raw: String,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl Record {
+    fn from_json_line(line: &str) -> Self {
+        let json = match serde_json::from_str(line) {
+            Ok(Value::Object(object)) => Some(object),
+            _ => None,
+        };
+        let timestamp = json
+            .as_ref()
+            .and_then(|object| object.get("timestamp")?.as_str().and_then(parse_timestamp));
+        Self {
+            json,
+            raw: line.to_string(),
+            timestamp,
+        }
+    }
+
+    fn from_csv_row(header: &[String], row: &[String]) -> Self {
+        let mut object = Map::new();
+        let mut timestamp = None;
+        for (column, cell) in header.iter().zip(row) {
+            if cell.is_empty() {
+                continue;
+            }
+            match column.as_str() {
+                "timestamp" | "ts" => {
+                    timestamp = parse_timestamp(cell);
+                    let value = timestamp.map_or_else(|| cell.clone(), |ts| ts.to_rfc3339());
+                    object.insert("timestamp".to_string(), Value::String(value));
+                }
+                c if NUMERIC_COLUMNS.contains(&c) => {
+                    let value = cell
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|n| serde_json::Number::from_f64(n).map(Value::Number))
+                        .unwrap_or_else(|| Value::String(cell.clone()));
+                    object.insert(c.to_string(), value);
+                }
+                c if TEXT_COLUMNS.contains(&c) => {
+                    object.insert(c.to_string(), Value::String(cell.clone()));
+                }
+                _ => {}
+            }
+        }
+        Self {
+            raw: Value::Object(object.clone()).to_string(),
+            json: Some(object),
+            timestamp,
+        }
+    }
+
+    fn device_id(&self) -> &str {
+        self.json
+            .as_ref()
+            .and_then(|object| object.get("device_id")?.as_str())
+            .unwrap_or("unknown")
+    }
+
+    /// The payload to publish, with its timestamp replaced when `timestamp` is set
+    fn payload(&self, timestamp: Option<DateTime<Utc>>) -> String {
+        match (&self.json, timestamp) {
+            (Some(object), Some(timestamp)) => {
+                let mut object = object.clone();
+                object.insert("timestamp".to_string(), Value::String(timestamp.to_rfc3339()));
+                Value::Object(object).to_string()
+            }
+            _ => self.raw.clone(),
+        }
+    }
+}
+
+/// RFC 3339, or PostgreSQL's text form such as `2025-10-05 12:00:00.5+00`
+fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    DateTime::parse_from_rfc3339(s)
+        .or_else(|_| DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f%#z"))
+        .map(|ts| ts.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+                .ok()
+                .map(|ts| ts.and_utc())
+        })
+}
+
+/// Splits one CSV line, honouring double-quoted fields with `""` escapes
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+pub fn detect_format(path: &Path) -> InputFormat {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => InputFormat::Csv,
+        _ => InputFormat::Ndjson,
+    }
+}
+
+/// When to publish each record
+enum Timing {
+    /// Recorded spacing between timestamps, divided by `speed`
+    Recorded { speed: f64 },
+    Rate(Pacer),
+    Fast,
+}
+
+/// Maps recorded timestamps onto the replay: the first record is published
+/// (and, with `shift`, stamped) at `started`
+struct Timeline {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    first: Option<DateTime<Utc>>,
+}
+
+impl Timeline {
+    /// Time since the first recorded timestamp
+    fn offset(&mut self, timestamp: DateTime<Utc>) -> chrono::Duration {
+        let first = *self.first.get_or_insert(timestamp);
+        timestamp - first
+    }
+
+    fn publish_at(&mut self, timestamp: DateTime<Utc>, speed: f64) -> Instant {
+        let offset = self.offset(timestamp).to_std().unwrap_or_default();
+        self.started + offset.div_f64(speed)
+    }
+
+    fn shifted(&mut self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        self.started_at + self.offset(timestamp)
+    }
+}
+
+/// Publishes each record of the file as one message, in file order
+pub async fn replay(publisher: &Publisher, args: &ReplayArgs, stats: &mut Stats) -> Result<(), String> {
+    let file = File::open(&args.file)
+        .await
+        .map_err(|e| format!("cannot open {}: {}", args.file.display(), e))?;
+    let format = args.input_format.unwrap_or_else(|| detect_format(&args.file));
+    let mut lines = BufReader::new(file).lines();
+
+    let mut timing = match (args.fast, args.rate) {
+        (true, _) => Timing::Fast,
+        (false, Some(rate)) => Timing::Rate(Pacer::new(rate)),
+        (false, None) => Timing::Recorded { speed: args.speed },
+    };
+    let mut timeline = Timeline {
+        started: Instant::now(),
+        started_at: Utc::now(),
+        first: None,
+    };
+    let mut header: Option<Vec<String>> = None;
+    info!("Replaying {} as {:?}", args.file.display(), format);
+
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| format!("cannot read {}: {}", args.file.display(), e))?
+    {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = match format {
+            InputFormat::Ndjson => Record::from_json_line(line),
+            InputFormat::Csv => match &header {
+                None => {
+                    header = Some(split_csv(line).iter().map(|c| c.trim().to_lowercase()).collect());
+                    continue;
+                }
+                Some(header) => Record::from_csv_row(header, &split_csv(line)),
+            },
+        };
+        if args.count.is_some_and(|count| stats.published + stats.failed >= count) {
+            break;
+        }
+        stats.readings += 1;
+
+        if let (Timing::Recorded { speed }, Some(timestamp)) = (&timing, record.timestamp) {
+            tokio::time::sleep_until(timeline.publish_at(timestamp, *speed)).await;
+        }
+        let timestamp = match args.timestamps {
+            TimestampMode::Keep => None,
+            TimestampMode::Shift => record.timestamp.map(|ts| timeline.shifted(ts)),
+            TimestampMode::Now => Some(Utc::now()),
+        };
+
+        let topic = render_topic(&args.topic, record.device_id(), "");
+        match publisher.publish(&topic, record.payload(timestamp)).await {
+            Ok(()) => stats.published += 1,
+            Err(e) => {
+                stats.failed += 1;
+                warn!("Failed to publish: {}", e);
+            }
+        }
+
+        match &mut timing {
+            Timing::Rate(pacer) => pacer.tick().await,
+            Timing::Fast if stats.readings.is_multiple_of(1000) => tokio::task::yield_now().await,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ts(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_split_csv() {
+        assert_eq!(split_csv("a,b,,c"), vec!["a", "b", "", "c"]);
+        assert_eq!(split_csv(r#""dev,1","say ""hi""",3"#), vec!["dev,1", r#"say "hi""#, "3"]);
+    }
+
+    #[test]
+    fn test_csv_record() {
+        // As written by \copy (SELECT * FROM telemetry) TO 'out.csv' CSV HEADER
+        let header: Vec<String> = split_csv("id,device_id,ts,temperature,humidity,battery,inserted_at")
+            .into_iter()
+            .collect();
+        let record = Record::from_csv_row(
+            &header,
+            &split_csv("7,dev-1,2025-10-05 12:00:00.5+00,21.5,55,oops,2025-10-05 12:00:01+00"),
+        );
+        assert_eq!(record.timestamp, Some(ts("2025-10-05T12:00:00.5Z")));
+        let payload: Value = serde_json::from_str(&record.payload(None)).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "device_id": "dev-1",
+                "timestamp": "2025-10-05T12:00:00.500+00:00",
+                "temperature": 21.5,
+                "humidity": 55.0,
+                "battery": "oops"
+            })
+        );
+        assert_eq!(record.device_id(), "dev-1");
+    }
+
+    #[test]
+    fn test_json_record() {
+        let record = Record::from_json_line(r#"{"device_id":"dev-2","timestamp":"2025-10-05T12:00:00Z","battery":80}"#);
+        assert_eq!(record.timestamp, Some(ts("2025-10-05T12:00:00Z")));
+        // Kept byte for byte unless the timestamp is rewritten
+        assert_eq!(record.payload(None), record.raw);
+        let rewritten: Value =
+            serde_json::from_str(&record.payload(Some(ts("2026-01-01T00:00:00Z")))).unwrap();
+        assert_eq!(rewritten["timestamp"], "2026-01-01T00:00:00+00:00");
+        assert_eq!(rewritten["battery"], 80);
+
+        // Captured garbage is replayed as is
+        let record = Record::from_json_line(r#"{"device_id":"dev-3","#);
+        assert_eq!(record.payload(Some(Utc::now())), r#"{"device_id":"dev-3","#);
+        assert_eq!(record.device_id(), "unknown");
+    }
+
+    #[test]
+    fn test_timeline() {
+        let started = Instant::now();
+        let started_at = ts("2026-10-18T09:00:00Z");
+        let mut timeline = Timeline {
+            started,
+            started_at,
+            first: None,
+        };
+
+        assert_eq!(timeline.publish_at(ts("2025-10-05T12:00:00Z"), 1.0), started);
+        assert_eq!(
+            timeline.publish_at(ts("2025-10-05T12:00:10Z"), 2.0),
+            started + Duration::from_secs(5)
+        );
+        // Out-of-order records go out right away
+        assert_eq!(timeline.publish_at(ts("2025-10-05T11:59:00Z"), 1.0), started);
+        assert_eq!(
+            timeline.shifted(ts("2025-10-05T12:01:00Z")),
+            ts("2026-10-18T09:01:00Z")
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = Some(ts("2025-10-05T12:00:00Z"));
+        assert_eq!(parse_timestamp("2025-10-05T12:00:00Z"), expected);
+        assert_eq!(parse_timestamp("2025-10-05 14:00:00+02"), expected);
+        assert_eq!(parse_timestamp("2025-10-05 12:00:00"), expected);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::device::Device;
+use crate::faults::FaultConfig;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use rand::Rng;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Device fleets and how their readings behave, loaded from `SCENARIO`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Seed for reproducible readings; random when unset
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Simulated seconds per real second, e.g. 3600 to play a day in 24 seconds
+    #[serde(default = "default_time_scale")]
+    pub time_scale: f64,
+    pub fleets: Vec<Fleet>,
+    /// Fault injection rates applied to every device's messages
+    #[serde(default)]
+    pub faults: Option<FaultConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fleet {
+    pub name: String,
+    pub count: usize,
+    /// Device ids are `<prefix><n>`; defaults to `<name>-`
+    #[serde(default)]
+    pub device_prefix: Option<String>,
+    #[serde(default = "default_temperature")]
+    pub temperature: Sensor,
+    #[serde(default = "default_humidity")]
+    pub humidity: Sensor,
+    #[serde(default)]
+    pub battery: Battery,
+    #[serde(default)]
+    pub dropout: Option<Dropout>,
+    #[serde(default)]
+    pub reconnect_storm: Option<ReconnectStorm>,
+}
+
+/// How a measurement evolves over time
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum Model {
+    Constant {
+        value: f64,
+    },
+    /// Independent values every reading
+    Uniform { min: f64, max: f64 },
+    /// Daily cosine curve peaking at `peak_hour` (UTC), plus up to `noise` either way
+    Diurnal {
+        mean: f64,
+        amplitude: f64,
+        #[serde(default = "default_peak_hour")]
+        peak_hour: f64,
+        #[serde(default)]
+        noise: f64,
+    },
+    /// Moves at most `step` per reading, staying within `min..=max`
+    RandomWalk {
+        start: f64,
+        step: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Sensor {
+    #[serde(flatten)]
+    pub model: Model,
+    /// Constant per-device offset drawn from `-jitter..=jitter`, so devices differ
+    #[serde(default)]
+    pub jitter: f64,
+    /// Calibration error accumulating per simulated hour
+    #[serde(default)]
+    pub drift_per_hour: f64,
+    #[serde(default)]
+    pub stuck: Option<Stuck>,
+    #[serde(default)]
+    pub outliers: Option<Outliers>,
+}
+
+/// With `probability` per reading, the sensor repeats its last value for `duration_secs`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stuck {
+    pub probability: f64,
+    pub duration_secs: f64,
+}
+
+/// With `probability` per reading, a value from `min..max` replaces the real one
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Outliers {
+    pub probability: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Battery {
+    /// Initial charge is drawn from this range
+    #[serde(default = "default_battery_start")]
+    pub start: [f64; 2],
+    #[serde(default = "default_drain_per_hour")]
+    pub drain_per_hour: f64,
+    /// Charging starts below this level and stops when full; never when unset
+    #[serde(default)]
+    pub recharge_below: Option<f64>,
+    #[serde(default = "default_recharge_per_hour")]
+    pub recharge_per_hour: f64,
+}
+
+/// With `probability` per reading, a device goes silent for `min_secs..max_secs`
+/// and its readings in that time are lost
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dropout {
+    pub probability: f64,
+    pub min_secs: f64,
+    pub max_secs: f64,
+}
+
+/// Every `every_secs` the whole fleet is offline for `outage_secs`, then all
+/// devices reconnect at once and publish up to `backlog` buffered readings each
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectStorm {
+    pub every_secs: f64,
+    pub outage_secs: f64,
+    #[serde(default)]
+    pub backlog: usize,
+}
+
+fn default_time_scale() -> f64 {
+    1.0
+}
+
+fn default_peak_hour() -> f64 {
+    15.0
+}
+
+fn default_temperature() -> Sensor {
+    Sensor {
+        model: Model::Diurnal {
+            mean: 25.0,
+            amplitude: 5.0,
+            peak_hour: default_peak_hour(),
+            noise: 0.5,
+        },
+        jitter: 3.0,
+        drift_per_hour: 0.0,
+        stuck: None,
+        outliers: None,
+    }
+}
+
+fn default_humidity() -> Sensor {
+    Sensor {
+        model: Model::RandomWalk {
+            start: 55.0,
+            step: 0.5,
+            min: 30.0,
+            max: 80.0,
+        },
+        jitter: 10.0,
+        drift_per_hour: 0.0,
+        stuck: None,
+        outliers: None,
+    }
+}
+
+fn default_battery_start() -> [f64; 2] {
+    [50.0, 100.0]
+}
+
+fn default_drain_per_hour() -> f64 {
+    0.5
+}
+
+fn default_recharge_per_hour() -> f64 {
+    25.0
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Self {
+            start: default_battery_start(),
+            drain_per_hour: default_drain_per_hour(),
+            recharge_below: Some(10.0),
+            recharge_per_hour: default_recharge_per_hour(),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let scenario: Self = serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid scenario {}: {}", path.display(), e))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// One fleet of `devices` sensors `dev-0..` with default behavior and a few
+    /// out-of-range outliers, used when no scenario file is given
+    pub fn default_fleet(devices: usize) -> Self {
+        let mut temperature = default_temperature();
+        temperature.outliers = Some(Outliers {
+            probability: 0.05,
+            min: -50.0,
+            max: 100.0,
+        });
+        let mut humidity = default_humidity();
+        humidity.outliers = Some(Outliers {
+            probability: 0.05,
+            min: 0.0,
+            max: 100.0,
+        });
+        Self {
+            seed: None,
+            time_scale: default_time_scale(),
+            fleets: vec![Fleet {
+                name: "dev".to_string(),
+                count: devices,
+                device_prefix: None,
+                temperature,
+                humidity,
+                battery: Battery::default(),
+                dropout: None,
+                reconnect_storm: None,
+            }],
+            faults: None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.time_scale <= 0.0 {
+            return Err("time_scale must be positive".to_string());
+        }
+        if let Some(faults) = &self.faults {
+            faults.validate()?;
+        }
+        if self.fleets.iter().all(|f| f.count == 0) {
+            return Err("scenario has no devices".to_string());
+        }
+        for fleet in &self.fleets {
+            if let Some(storm) = &fleet.reconnect_storm {
+                if storm.outage_secs >= storm.every_secs {
+                    return Err(format!(
+                        "fleet {}: reconnect_storm outage_secs must be shorter than every_secs",
+                        fleet.name
+                    ));
+                }
+            }
+            if let Some(dropout) = &fleet.dropout {
+                if dropout.min_secs > dropout.max_secs {
+                    return Err(format!("fleet {}: dropout min_secs exceeds max_secs", fleet.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// All devices of all fleets, in fleet order
+    pub fn devices(&self, rng: &mut impl Rng) -> Vec<Device> {
+        let mut devices = Vec::new();
+        for fleet in &self.fleets {
+            let prefix = fleet
+                .device_prefix
+                .clone()
+                .unwrap_or_else(|| format!("{}-", fleet.name));
+            for n in 0..fleet.count {
+                devices.push(Device::new(format!("{}{}", prefix, n), fleet, rng));
+            }
+        }
+        devices
+    }
+}
+
+/// Simulated time. It advances by one reading interval per device turn
+/// rather than with the wall clock, so a seeded run produces the same readings
+/// however fast it is published.
+#[derive(Debug, Clone, Copy)]
+pub struct SimClock {
+    origin: DateTime<Utc>,
+    secs_per_turn: f64,
+}
+
+impl SimClock {
+    /// `rate` device turns per second, played `time_scale` times faster
+    pub fn new(origin: DateTime<Utc>, time_scale: f64, rate: u64) -> Self {
+        Self {
+            origin,
+            secs_per_turn: time_scale / rate.max(1) as f64,
+        }
+    }
+
+    pub fn at_turn(&self, turn: u64) -> SimTime {
+        let elapsed_secs = turn as f64 * self.secs_per_turn;
+        SimTime {
+            elapsed_secs,
+            at: self.origin + ChronoDuration::milliseconds((elapsed_secs * 1000.0) as i64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SimTime {
+    /// Simulated seconds since start
+    pub elapsed_secs: f64,
+    /// Simulated time of day, which drives diurnal curves
+    pub at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_example_scenario() {
+        let scenario: Scenario =
+            serde_json::from_str(include_str!("../../deploy/scenario.example.json")).unwrap();
+        scenario.validate().unwrap();
+
+        let devices = scenario.devices(&mut StdRng::seed_from_u64(1));
+        let expected: usize = scenario.fleets.iter().map(|f| f.count).sum();
+        assert_eq!(devices.len(), expected);
+        assert_eq!(devices[0].id, "gh-0");
+    }
+
+    #[test]
+    fn test_invalid_scenario() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{ "fleets": [{ "name": "a", "count": 1, "reconnect_storm": { "every_secs": 60, "outage_secs": 60 } }] }"#,
+        )
+        .unwrap();
+        assert!(scenario.validate().is_err());
+
+        let result: Result<Scenario, _> = serde_json::from_str(
+            r#"{ "fleets": [{ "name": "a", "count": 1, "temperature": { "model": "sine" } }] }"#,
+        );
+        assert!(result.is_err());
+    }
+}
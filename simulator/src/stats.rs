@@ -0,0 +1,173 @@
+use crate::cli::ReportFormat;
+use crate::faults::{Fault, FaultSummary};
+use crate::pool::ConnectionSummary;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+/// Running totals of one simulator run
+#[derive(Debug)]
+pub struct Stats {
+    pub started: Instant,
+    /// Readings generated, or lines read when replaying
+    pub readings: u64,
+    pub published: u64,
+    pub failed: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            readings: 0,
+            published: 0,
+            failed: 0,
+        }
+    }
+}
+
+/// Final (or periodic) stats, printed as text or JSON
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub command: &'static str,
+    /// False for periodic reports while still running
+    pub finished: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub elapsed_secs: f64,
+    pub readings: u64,
+    pub published: u64,
+    pub failed: u64,
+    /// Broker acknowledgements; absent at QoS 0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acknowledged: Option<u64>,
+    pub messages_per_sec: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub faults: BTreeMap<Fault, u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_invalid: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_duplicates: Option<u64>,
+    /// Present when devices have their own connections
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connections: Option<ConnectionSummary>,
+    #[serde(skip)]
+    fault_summary: Option<FaultSummary>,
+}
+
+impl Report {
+    pub fn new(
+        command: &'static str,
+        stats: &Stats,
+        seed: Option<u64>,
+        acknowledged: Option<u64>,
+        faults: Option<FaultSummary>,
+    ) -> Self {
+        let elapsed_secs = stats.started.elapsed().as_secs_f64();
+        let faults = faults.filter(|summary| !summary.injected.is_empty());
+        Self {
+            command,
+            finished: true,
+            seed,
+            elapsed_secs,
+            readings: stats.readings,
+            published: stats.published,
+            failed: stats.failed,
+            acknowledged,
+            messages_per_sec: if elapsed_secs > 0.0 {
+                stats.published as f64 / elapsed_secs
+            } else {
+                0.0
+            },
+            faults: faults.as_ref().map(|s| s.injected.clone()).unwrap_or_default(),
+            expected_invalid: faults.as_ref().map(FaultSummary::expected_invalid),
+            expected_duplicates: faults.as_ref().map(|s| s.count(Fault::Duplicate)),
+            connections: None,
+            fault_summary: faults,
+        }
+    }
+
+    /// Marks a periodic report taken while still running
+    pub fn in_progress(mut self) -> Self {
+        self.finished = false;
+        self
+    }
+
+    pub fn print(&self, format: ReportFormat) {
+        match format {
+            ReportFormat::Text => println!("{}", self),
+            ReportFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Failed to serialize report: {}", e),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.finished { "finished" } else { "running" };
+        write!(f, "{} {} after {:.1}s", self.command, state, self.elapsed_secs)?;
+        if let Some(seed) = self.seed {
+            write!(f, " (seed {})", seed)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  readings:      {}", self.readings)?;
+        writeln!(f, "  published:     {} ({:.1} msg/s)", self.published, self.messages_per_sec)?;
+        write!(f, "  failed:        {}", self.failed)?;
+        if let Some(acknowledged) = self.acknowledged {
+            write!(f, "\n  acknowledged:  {}", acknowledged)?;
+        }
+        if let Some(connections) = &self.connections {
+            write!(f, "\n{}", connections)?;
+        }
+        if let Some(summary) = &self.fault_summary {
+            write!(f, "\n{}", summary)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_formats() {
+        let stats = Stats {
+            started: Instant::now(),
+            readings: 100,
+            published: 103,
+            failed: 1,
+        };
+        let mut faults = FaultSummary {
+            readings: 100,
+            ..Default::default()
+        };
+        faults.injected.insert(Fault::Duplicate, 3);
+        faults.injected.insert(Fault::MalformedJson, 2);
+
+        let report = Report::new("burst", &stats, Some(42), Some(103), Some(faults));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["seed"], 42);
+        assert_eq!(json["faults"]["duplicate"], 3);
+        assert_eq!(json["expected_invalid"], 2);
+        assert_eq!(json["expected_duplicates"], 3);
+
+        let text = report.to_string();
+        assert_eq!(json["finished"], true);
+        assert!(text.starts_with("burst finished after"));
+        assert!(report.in_progress().to_string().starts_with("burst running after"));
+        assert!(text.contains("(seed 42)"));
+        assert!(text.contains("acknowledged:  103"));
+        assert!(text.contains("malformed_json"));
+
+        // Nothing injected and QoS 0: no fault or ack fields
+        let report = Report::new("run", &stats, None, None, Some(FaultSummary::default()));
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json.get("faults").is_none());
+        assert!(json.get("acknowledged").is_none());
+        assert!(json.get("seed").is_none());
+    }
+}
@@ -0,0 +1,566 @@
+use crate::cli::{render_topic, VerifyArgs, Via};
+use crate::http::HttpClient;
+use crate::publisher::{Pacer, Publisher};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use telemetry_model::Telemetry;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Rows per REST page; the API's maximum
+const PAGE_SIZE: usize = 1000;
+
+/// Measurements read back are compared with what was sent within this margin
+const TOLERANCE: f64 = 1e-9;
+
+/// A message is identified by its device and timestamp in microseconds, the
+/// precision Postgres stores
+type Key = (String, i64);
+
+/// A reading as read back from the database or the REST API
+#[derive(Debug, Clone, Deserialize)]
+struct Row {
+    device_id: String,
+    timestamp: DateTime<Utc>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    battery: Option<f64>,
+}
+
+impl Row {
+    fn key(&self) -> Key {
+        (self.device_id.clone(), self.timestamp.timestamp_micros())
+    }
+
+    fn matches(&self, sent: &Telemetry) -> bool {
+        let same = |value: Option<f64>, expected: f64| value.is_some_and(|v| (v - expected).abs() < TOLERANCE);
+        same(self.temperature, sent.temperature)
+            && same(self.humidity, sent.humidity)
+            && same(self.battery, sent.battery)
+    }
+}
+
+/// Where delivered messages are looked up
+enum Source {
+    Db(PgPool),
+    Rest(HttpClient),
+}
+
+impl Source {
+    async fn connect(args: &VerifyArgs) -> Result<Self, String> {
+        match args.via {
+            Via::Db => {
+                let pool = PgPoolOptions::new()
+                    .max_connections(2)
+                    .acquire_timeout(Duration::from_secs(10))
+                    .connect(&args.database_url)
+                    .await
+                    .map_err(|e| format!("cannot connect to database: {}", e))?;
+                Ok(Source::Db(pool))
+            }
+            Via::Rest => Ok(Source::Rest(HttpClient::new(&args.api_url, args.api_key.clone())?)),
+        }
+    }
+
+    /// Rows of devices starting with `prefix`, at or after `since` when given
+    async fn fetch(&self, prefix: &str, since: Option<DateTime<Utc>>) -> Result<Vec<Row>, String> {
+        match self {
+            Source::Db(pool) => {
+                let rows: Vec<(String, DateTime<Utc>, f64, f64, f64)> = sqlx::query_as(
+                    "SELECT device_id, ts, temperature, humidity, battery FROM telemetry \
+                     WHERE device_id LIKE $1 AND ($2::timestamptz IS NULL OR ts >= $2)",
+                )
+                .bind(format!("{}%", prefix))
+                .bind(since)
+                .fetch_all(pool)
+                .await
+                .map_err(|e| format!("telemetry query failed: {}", e))?;
+                Ok(rows
+                    .into_iter()
+                    .map(|(device_id, timestamp, temperature, humidity, battery)| Row {
+                        device_id,
+                        timestamp,
+                        temperature: Some(temperature),
+                        humidity: Some(humidity),
+                        battery: Some(battery),
+                    })
+                    .collect())
+            }
+            Source::Rest(client) => {
+                let mut rows = Vec::new();
+                loop {
+                    let mut query = vec![
+                        ("device_prefix", prefix.to_string()),
+                        ("limit", PAGE_SIZE.to_string()),
+                        ("offset", rows.len().to_string()),
+                    ];
+                    if let Some(since) = since {
+                        query.push(("start", since.to_rfc3339()));
+                    }
+                    let page = client.get_json("/api/v1/telemetry", &query).await?;
+                    let page: Vec<Row> = serde_json::from_value(page["data"].clone())
+                        .map_err(|e| format!("unexpected telemetry response: {}", e))?;
+                    let last_page = page.len() < PAGE_SIZE;
+                    rows.extend(page);
+                    if last_page {
+                        return Ok(rows);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One published message and when it first showed up in a query
+#[derive(Debug)]
+struct Sent {
+    reading: Telemetry,
+    published: Instant,
+    latency: Option<Duration>,
+}
+
+/// Everything published so far, in publish order
+#[derive(Debug, Default)]
+struct Tracker {
+    sent: Vec<Sent>,
+    index: HashMap<Key, usize>,
+    /// Every message before this one has been seen or given up on
+    oldest_pending: usize,
+}
+
+impl Tracker {
+    fn record(&mut self, reading: Telemetry, published: Instant) {
+        let key = (reading.device_id.clone(), reading.timestamp.timestamp_micros());
+        self.index.insert(key, self.sent.len());
+        self.sent.push(Sent {
+            reading,
+            published,
+            latency: None,
+        });
+    }
+
+    /// Timestamp to query from: that of the oldest message not yet seen and
+    /// published less than `give_up` ago; `None` when nothing is pending
+    fn cursor(&mut self, now: Instant, give_up: Duration) -> Option<DateTime<Utc>> {
+        while let Some(sent) = self.sent.get(self.oldest_pending) {
+            if sent.latency.is_none() && now.duration_since(sent.published) < give_up {
+                return Some(sent.reading.timestamp);
+            }
+            self.oldest_pending += 1;
+        }
+        None
+    }
+
+    fn mark_seen(&mut self, rows: &[Row], now: Instant) {
+        for row in rows {
+            if let Some(&i) = self.index.get(&row.key()) {
+                let sent = &mut self.sent[i];
+                sent.latency.get_or_insert(now.duration_since(sent.published));
+            }
+        }
+    }
+
+    fn all_seen(&self) -> bool {
+        self.sent.iter().all(|sent| sent.latency.is_some())
+    }
+}
+
+/// Delivery counts from the final read-back of every row of the run
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Tally {
+    pub delivered: u64,
+    pub lost: u64,
+    /// Rows beyond the first for the same message
+    pub duplicates: u64,
+    /// Rows whose measurements differ from what was sent
+    pub mismatched: u64,
+    /// Rows of the run's devices that match no published message
+    pub unexpected: u64,
+    /// Delivered, but only found after `--settle`
+    pub late: u64,
+}
+
+impl Tally {
+    fn new(tracker: &Tracker, rows: &[Row]) -> Self {
+        let mut tally = Tally::default();
+        let mut copies = vec![0u64; tracker.sent.len()];
+        for row in rows {
+            match tracker.index.get(&row.key()) {
+                Some(&i) => {
+                    copies[i] += 1;
+                    if !row.matches(&tracker.sent[i].reading) {
+                        tally.mismatched += 1;
+                    }
+                }
+                None => tally.unexpected += 1,
+            }
+        }
+        for (sent, copies) in tracker.sent.iter().zip(copies) {
+            match copies {
+                0 => tally.lost += 1,
+                n => {
+                    tally.delivered += 1;
+                    tally.duplicates += n - 1;
+                    if sent.latency.is_none() {
+                        tally.late += 1;
+                    }
+                }
+            }
+        }
+        tally
+    }
+}
+
+/// Publish-to-queryable latency in milliseconds, over messages seen in time
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Latency {
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Latency {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let ms = |q: f64| percentile(&samples, q).map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+        Self {
+            samples: samples.len(),
+            p50_ms: ms(0.50),
+            p95_ms: ms(0.95),
+            p99_ms: ms(0.99),
+            max_ms: ms(1.0),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], q: f64) -> Option<Duration> {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+}
+
+/// Outcome of a verification run, printed as text or JSON
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub run_id: String,
+    pub passed: bool,
+    pub published: u64,
+    pub failed: u64,
+    pub publish_secs: f64,
+    pub messages_per_sec: f64,
+    #[serde(flatten)]
+    pub tally: Tally,
+    pub latency: Latency,
+    pub violations: Vec<String>,
+}
+
+impl VerifyReport {
+    fn new(run_id: String, tracker: &Tracker, failed: u64, publish_time: Duration, rows: &[Row], args: &VerifyArgs) -> Self {
+        let published = tracker.sent.len() as u64;
+        let tally = Tally::new(tracker, rows);
+        let latency = Latency::new(tracker.sent.iter().filter_map(|sent| sent.latency).collect());
+        let violations = violations(published, &tally, &latency, args);
+        Self {
+            run_id,
+            passed: violations.is_empty(),
+            published,
+            failed,
+            publish_secs: publish_time.as_secs_f64(),
+            messages_per_sec: published as f64 / publish_time.as_secs_f64().max(f64::EPSILON),
+            tally,
+            latency,
+            violations,
+        }
+    }
+
+    pub fn print(&self, format: crate::cli::ReportFormat) {
+        match format {
+            crate::cli::ReportFormat::Text => println!("{}", self),
+            crate::cli::ReportFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Failed to serialize report: {}", e),
+            },
+        }
+    }
+}
+
+/// Broken SLOs, as one line each
+fn violations(published: u64, tally: &Tally, latency: &Latency, args: &VerifyArgs) -> Vec<String> {
+    let mut violations = Vec::new();
+    if published == 0 {
+        violations.push("nothing was published".to_string());
+        return violations;
+    }
+    let fraction = |n: u64| n as f64 / published as f64;
+    if fraction(tally.lost) > args.max_loss {
+        violations.push(format!(
+            "lost {} of {} messages ({:.3}%), limit {:.3}%",
+            tally.lost,
+            published,
+            fraction(tally.lost) * 100.0,
+            args.max_loss * 100.0
+        ));
+    }
+    if fraction(tally.duplicates) > args.max_duplicates {
+        violations.push(format!(
+            "{} duplicate rows ({:.3}%), limit {:.3}%",
+            tally.duplicates,
+            fraction(tally.duplicates) * 100.0,
+            args.max_duplicates * 100.0
+        ));
+    }
+    if tally.mismatched > 0 {
+        violations.push(format!("{} rows differ from what was published", tally.mismatched));
+    }
+    if tally.late > 0 {
+        violations.push(format!(
+            "{} messages became queryable only after {:?}",
+            tally.late, args.settle
+        ));
+    }
+    let limits = [("p50", latency.p50_ms, args.max_p50), ("p99", latency.p99_ms, Some(args.max_p99))];
+    for (name, actual_ms, limit) in limits {
+        if let Some(limit) = limit.filter(|limit| actual_ms > limit.as_secs_f64() * 1000.0) {
+            violations.push(format!("{} latency {:.0}ms, limit {:?}", name, actual_ms, limit));
+        }
+    }
+    violations
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.passed { "PASSED" } else { "FAILED" };
+        writeln!(f, "verify {} (run {})", verdict, self.run_id)?;
+        writeln!(
+            f,
+            "  published:     {} in {:.1}s ({:.1} msg/s), {} failed",
+            self.published, self.publish_secs, self.messages_per_sec, self.failed
+        )?;
+        writeln!(f, "  delivered:     {}", self.tally.delivered)?;
+        writeln!(f, "  lost:          {}", self.tally.lost)?;
+        writeln!(f, "  duplicates:    {}", self.tally.duplicates)?;
+        writeln!(f, "  mismatched:    {}", self.tally.mismatched)?;
+        writeln!(f, "  unexpected:    {}", self.tally.unexpected)?;
+        writeln!(f, "  late:          {}", self.tally.late)?;
+        write!(
+            f,
+            "  latency:       p50 {:.0}ms, p95 {:.0}ms, p99 {:.0}ms, max {:.0}ms ({} samples)",
+            self.latency.p50_ms, self.latency.p95_ms, self.latency.p99_ms, self.latency.max_ms, self.latency.samples
+        )?;
+        for violation in &self.violations {
+            write!(f, "\n  violated:      {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Publishes uniquely tagged readings at `--rate` while polling for them,
+/// then reads every row of the run back and checks the SLOs
+pub async fn verify(publisher: &Publisher, args: &VerifyArgs) -> Result<VerifyReport, String> {
+    let source = Source::connect(args).await?;
+    let run_id = format!("{:08x}", rand::random::<u32>());
+    let prefix = format!("e2e-{}-", run_id);
+    info!(
+        "Verifying run {}: {} msg/s for {:?} over {} devices, reading back via {:?}",
+        run_id, args.rate, args.duration, args.devices, args.via
+    );
+
+    let tracker = Mutex::new(Tracker::default());
+    let published = AtomicBool::new(false);
+    let mut failed = 0u64;
+    let started = Instant::now();
+
+    let publishing = async {
+        let mut pacer = Pacer::new(args.rate);
+        let mut last_micros = 0i64;
+        let mut n = 0u64;
+        while started.elapsed() < args.duration {
+            let device_id = format!("{}{}", prefix, n % args.devices);
+            n += 1;
+            // Strictly increasing timestamps keep every (device, ts) unique
+            let micros = Utc::now().timestamp_micros().max(last_micros + 1);
+            last_micros = micros;
+            let reading = reading(device_id, micros);
+            let topic = render_topic(&args.topic, &reading.device_id, "e2e");
+            let payload = serde_json::to_string(&reading).map_err(|e| e.to_string())?;
+
+            let sent_at = Instant::now();
+            match publisher.publish(&topic, payload).await {
+                Ok(()) => tracker.lock().unwrap().record(reading, sent_at),
+                Err(e) => {
+                    failed += 1;
+                    warn!("Failed to publish: {}", e);
+                }
+            }
+            pacer.tick().await;
+        }
+        published.store(true, Ordering::Relaxed);
+        Ok::<_, String>(started.elapsed())
+    };
+
+    let polling = async {
+        let mut settle_deadline = None;
+        loop {
+            tokio::time::sleep(args.poll_interval).await;
+            let done = published.load(Ordering::Relaxed);
+            if done && settle_deadline.is_none() {
+                settle_deadline = Some(Instant::now() + args.settle);
+            }
+            let cursor = tracker.lock().unwrap().cursor(Instant::now(), args.settle);
+            if let Some(cursor) = cursor {
+                match source.fetch(&prefix, Some(cursor)).await {
+                    Ok(rows) => tracker.lock().unwrap().mark_seen(&rows, Instant::now()),
+                    Err(e) => warn!("Poll failed: {}", e),
+                }
+            }
+            let all_seen = tracker.lock().unwrap().all_seen();
+            if done && (all_seen || settle_deadline.is_some_and(|deadline| Instant::now() >= deadline)) {
+                break;
+            }
+        }
+    };
+
+    let (publish_time, ()) = tokio::join!(publishing, polling);
+    let publish_time = publish_time?;
+
+    let rows = source.fetch(&prefix, None).await?;
+    let tracker = tracker.into_inner().unwrap();
+    Ok(VerifyReport::new(run_id, &tracker, failed, publish_time, &rows, args))
+}
+
+/// A valid reading with values rounded to two decimals
+fn reading(device_id: String, micros: i64) -> Telemetry {
+    let mut rng = rand::thread_rng();
+    let mut value = |low: f64, high: f64| (rng.gen_range(low..high) * 100.0).round() / 100.0;
+    Telemetry::new(
+        device_id,
+        DateTime::from_timestamp_micros(micros).unwrap_or_else(Utc::now),
+        value(15.0, 35.0),
+        value(30.0, 80.0),
+        value(20.0, 100.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Command};
+    use clap::Parser;
+
+    fn row(sent: &Telemetry) -> Row {
+        Row {
+            device_id: sent.device_id.clone(),
+            timestamp: sent.timestamp,
+            temperature: Some(sent.temperature),
+            humidity: Some(sent.humidity),
+            battery: Some(sent.battery),
+        }
+    }
+
+    fn args(extra: &[&str]) -> VerifyArgs {
+        let cli = Cli::try_parse_from(["simulator", "verify"].iter().chain(extra)).unwrap();
+        match cli.command() {
+            Command::Verify(args) => args,
+            other => panic!("expected verify, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tally_and_latency() {
+        let now = Instant::now();
+        let mut tracker = Tracker::default();
+        let readings: Vec<Telemetry> = (0..5).map(|i| reading(format!("e2e-x-{}", i % 2), 1_000_000 + i)).collect();
+        for (i, reading) in readings.iter().enumerate() {
+            tracker.record(reading.clone(), now + Duration::from_millis(i as u64));
+        }
+
+        // First poll sees messages 0 and 1, so the cursor moves to message 2
+        let seen_at = now + Duration::from_millis(100);
+        tracker.mark_seen(&[row(&readings[0]), row(&readings[1])], seen_at);
+        assert_eq!(tracker.sent[1].latency, Some(Duration::from_millis(99)));
+        assert_eq!(tracker.cursor(seen_at, Duration::from_secs(30)), Some(readings[2].timestamp));
+        // Message 2 is given up on once published longer ago than the settle time
+        assert_eq!(
+            tracker.cursor(now + Duration::from_secs(60), Duration::from_secs(30)),
+            None
+        );
+
+        let mut changed = row(&readings[3]);
+        changed.battery = Some(1.0);
+        let stranger = row(&reading("e2e-x-9".to_string(), 5));
+        let rows = [
+            row(&readings[0]),
+            row(&readings[0]),
+            row(&readings[1]),
+            changed,
+            row(&readings[4]),
+            stranger,
+        ];
+        assert_eq!(
+            Tally::new(&tracker, &rows),
+            Tally {
+                delivered: 4,
+                lost: 1,
+                duplicates: 1,
+                mismatched: 1,
+                unexpected: 1,
+                late: 2,
+            }
+        );
+
+        let latency = Latency::new(tracker.sent.iter().filter_map(|sent| sent.latency).collect());
+        assert_eq!(latency.samples, 2);
+        assert_eq!(latency.p50_ms, 99.0);
+        assert_eq!(latency.max_ms, 100.0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.5), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&samples, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&samples, 1.0), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn test_violations() {
+        let latency = Latency {
+            samples: 100,
+            p50_ms: 200.0,
+            p99_ms: 900.0,
+            ..Default::default()
+        };
+        let clean = Tally {
+            delivered: 1000,
+            ..Default::default()
+        };
+        assert!(violations(1000, &clean, &latency, &args(&[])).is_empty());
+
+        let lossy = Tally {
+            delivered: 990,
+            lost: 10,
+            duplicates: 3,
+            ..Default::default()
+        };
+        let found = violations(1000, &lossy, &latency, &args(&["--max-p50", "100ms"]));
+        assert_eq!(found.len(), 3, "{:?}", found);
+        assert!(found[0].starts_with("lost 10 of 1000"));
+        assert!(found[2].starts_with("p50 latency 200ms"));
+
+        let tolerant = args(&["--max-loss", "0.01", "--max-duplicates", "0.01", "--max-p99", "500ms"]);
+        let found = violations(1000, &lossy, &latency, &tolerant);
+        assert_eq!(found, vec!["p99 latency 900ms, limit 500ms".to_string()]);
+
+        assert_eq!(violations(0, &Tally::default(), &Latency::default(), &args(&[])).len(), 1);
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// IoT device telemetry data, as published on `telemetry/<device_id>`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Telemetry {
+    pub device_id: String,
+    pub timestamp: DateTime<Utc>,
+    #[cfg_attr(feature = "openapi", schema(minimum = -50, maximum = 100))]
+    pub temperature: f64,
+    #[cfg_attr(feature = "openapi", schema(minimum = 0, maximum = 100))]
+    pub humidity: f64,
+    #[cfg_attr(feature = "openapi", schema(minimum = 0, maximum = 100))]
+    pub battery: f64,
+}
+
+impl Telemetry {
+    pub fn new(
+        device_id: impl Into<String>,
+        timestamp: DateTime<Utc>,
+        temperature: f64,
+        humidity: f64,
+        battery: f64,
+    ) -> Self {
+        Self {
+            device_id: device_id.into(),
+            timestamp,
+            temperature,
+            humidity,
+            battery,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_format() {
+        let telemetry = Telemetry::new("dev-1", "2025-10-05T12:00:00Z".parse().unwrap(), 21.5, 55.0, 80.0);
+        assert_eq!(
+            serde_json::to_string(&telemetry).unwrap(),
+            r#"{"device_id":"dev-1","timestamp":"2025-10-05T12:00:00Z","temperature":21.5,"humidity":55.0,"battery":80.0}"#
+        );
+
+        // Fields the ingestor reads from the payload itself, like `priority`, are ignored
+        let parsed: Telemetry = serde_json::from_str(
+            r#"{"device_id":"dev-1","timestamp":"2025-10-05T12:00:00Z","temperature":21.5,"humidity":55,"battery":80,"priority":"alarm"}"#,
+        )
+        .unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), serde_json::to_string(&telemetry).unwrap());
+    }
+}